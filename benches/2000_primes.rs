@@ -1,6 +1,7 @@
 use criterion::{criterion_group, criterion_main, Criterion};
 use stack_machine::{
-    interpreter::{Interpreter, InterpreterEvent},
+    assembler::assemble,
+    interpreter::{Interpreter, InterpreterConfig, InterpreterEvent},
     parser,
 };
 const PRIME_FINDER: [u8; 210] = [
@@ -13,6 +14,90 @@ const PRIME_FINDER: [u8; 210] = [
     0, 0, 0, 6, 22, 32, 11, 33, 1, 2, 8, 32, 12, 18, 9, 12, 33, 2, 2, 40, 120, 0, 0, 0, 0, 0, 0, 0,
     6, 22, 33, 2, 2, 40, 120, 0, 0, 0, 0, 0, 0, 0, 6, 22, 7,
 ];
+/// prime.xasm, stopping after 2000 primes without printing them
+const VERIFIABLE_PRIME_FINDER: &str = r#"
+GOTO :main
+:is_prime
+PUSH 0
+MLOAD
+PUSH 1
+:is_prime_loop
+DUP2
+DUP2
+LT
+PUSH 0
+EQ
+GOTONZ :is_prime_return_true
+DUP
+MLOAD
+DUP4
+MOD
+PUSH 0
+EQ
+GOTONZ :is_prime_return_false
+INC
+GOTO :is_prime_loop
+:is_prime_return_false
+POP
+POP
+POP
+PUSH 0
+RET
+:is_prime_return_true
+POP
+POP
+POP
+PUSH 1
+RET
+:main
+PUSH 1
+PUSH 0
+MSTORE
+PUSH 2
+PUSH 1
+MSTORE
+PUSH 3
+:check_loop
+PUSH 0
+MLOAD
+PUSH 1999
+LT
+GOTONZ :end
+DUP
+CALL :is_prime
+GOTONZ :check_loop_is_prime
+GOTO :check_loop_not_prime
+:check_loop_is_prime
+PUSH 0
+MLOAD
+INC
+DUP
+PUSH 0
+MSTORE
+DUP2
+SWAP
+MSTORE
+PUSH 2
+ADD
+GOTO :check_loop
+:check_loop_not_prime
+PUSH 2
+ADD
+GOTO :check_loop
+:end
+HALT
+"#;
+
+fn run(mut interpreter: Interpreter) {
+    loop {
+        match interpreter.next_instruction() {
+            Ok(InterpreterEvent::ProgramEnd) => break,
+            Ok(_) => {}
+            Err(e) => panic!("{e:?}"),
+        }
+    }
+}
+
 pub fn benchmark_primes(c: &mut Criterion) {
     let mut group = c.benchmark_group("small_sample_size");
     group.sample_size(20);
@@ -35,6 +120,22 @@ pub fn benchmark_primes(c: &mut Criterion) {
             }
         });
     });
+    // PRIME_FINDER has a branch that would leave two items on the stack if it were
+    // ever taken, so it cannot be verified. The same search without it can be.
+    let verifiable =
+        parser::parse_bytes_to_instructions(&assemble(VERIFIABLE_PRIME_FINDER).unwrap()).unwrap();
+    group.bench_function("Find 2000 primes (verifiable, checked)", |b| {
+        b.iter(|| run(Interpreter::new(verifiable.clone())));
+    });
+    group.bench_function("Find 2000 primes (verifiable, fast path)", |b| {
+        b.iter(|| {
+            let interpreter = InterpreterConfig::new()
+                .fast_path(true)
+                .build(verifiable.clone());
+            assert!(interpreter.is_verified());
+            run(interpreter)
+        });
+    });
     group.finish();
 }
 
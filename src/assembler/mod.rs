@@ -1,5 +1,29 @@
 pub mod preprocessor;
+pub mod source_map;
 use crate::opcode::Opcode;
+use preprocessor::PreprocessorError;
+use source_map::SourceMap;
+
+/// Assembles source all the way to bytecode
+pub fn assemble(source: &str) -> Result<Vec<u8>, PreprocessorError> {
+    assemble_with_map(source, "").map(|(bytecode, _)| bytecode)
+}
+
+/// Assembles source all the way to bytecode, with its source map. Lines in the map
+/// are attributed to `source_path`.
+pub fn assemble_with_map(
+    source: &str,
+    source_path: &str,
+) -> Result<(Vec<u8>, SourceMap), PreprocessorError> {
+    let s = preprocessor::parse_to_statements(source)?;
+    let s = preprocessor::to_stage2(s)?;
+    let labels = preprocessor::label_addresses(&s);
+    let s = preprocessor::to_stage3(s)?;
+    let lines = preprocessor::line_addresses(&s);
+    let bytecode = assemble_string_to_bytes(&preprocessor::compile_statements(s)?);
+    let source_map = SourceMap::new(labels).with_lines(source_path, lines);
+    Ok((bytecode, source_map))
+}
 
 pub fn assemble_string_to_bytes(input: &str) -> Vec<u8> {
     let mut bytecode = Vec::new();
@@ -11,43 +35,24 @@ pub fn assemble_string_to_bytes(input: &str) -> Vec<u8> {
             .unwrap_or_else(|| panic!("Empty line at line no {line_no}"));
         let opcode: Opcode = mnemonic.try_into().unwrap();
         match opcode {
-            Opcode::Push8 => {
+            Opcode::Push1
+            | Opcode::Push2
+            | Opcode::Push3
+            | Opcode::Push4
+            | Opcode::Push5
+            | Opcode::Push6
+            | Opcode::Push7
+            | Opcode::Push8 => {
                 let param_str = line_iter.next().unwrap_or_else(|| {
                     panic!("Operation {mnemonic} requires parameter at line no {line_no}")
                 });
-
                 let param = param_str.parse::<u64>().expect("Could not parse number");
-                bytecode.push(Opcode::Push8.into());
-                bytecode.extend_from_slice(&param.to_le_bytes());
-            }
-            Opcode::Push1 => {
-                let param_str = line_iter.next().unwrap_or_else(|| {
-                    panic!("Operation {mnemonic} requires parameter at line no {line_no}")
-                });
-                let param = param_str.parse::<u64>().expect("Could not parse number");
-                if param >= 256 {
-                    todo!()
+                let width = opcode.immediate_size();
+                if width < 8 && param >= 1 << (8 * width) {
+                    panic!("Parameter {param} does not fit in {mnemonic} at line no {line_no}")
                 }
-                bytecode.push(Opcode::Push1.into());
-
-                let byte = param as u8;
-                bytecode.extend_from_slice(&byte.to_le_bytes());
-            }
-            Opcode::Push2 => {
-                let param_str = line_iter.next().unwrap_or_else(|| {
-                    panic!("Operation {mnemonic} requires parameter at line no {line_no}")
-                });
-                let param = param_str.parse::<u64>().expect("Could not parse number");
-                if param >= 2u64.pow(16) {
-                    todo!()
-                }
-                bytecode.push(Opcode::Push2.into());
-
-                let byte = param as u16;
-                bytecode.extend_from_slice(&byte.to_le_bytes());
-            }
-            Opcode::Push3 | Opcode::Push4 | Opcode::Push5 | Opcode::Push6 | Opcode::Push7 => {
-                todo!()
+                bytecode.push(opcode.into());
+                bytecode.extend_from_slice(&param.to_le_bytes()[..width]);
             }
             _ => bytecode.push(opcode.into()),
         }
@@ -63,7 +68,10 @@ pub fn assemble_string_to_bytes(input: &str) -> Vec<u8> {
 }
 #[cfg(test)]
 mod test {
-    use super::assemble_string_to_bytes;
+    use super::{assemble, assemble_string_to_bytes, preprocessor};
+    use crate::interpreter::{Interpreter, InterpreterEvent};
+    use crate::opcode::Opcode;
+    use crate::parser::parse_bytes_to_instructions;
     const INPUT: &str = r#"PUSH8 123
 POP"#;
     #[test]
@@ -72,4 +80,102 @@ POP"#;
         let expected = [40, 123, 0, 0, 0, 0, 0, 0, 0, 1];
         assert_eq!(out, expected);
     }
+
+    #[test]
+    fn push_width_round_trip() {
+        let mut cases = vec![(0, 0), (1, 1)];
+        for width in 1..8 {
+            let boundary = 1u64 << (8 * width);
+            cases.push((boundary - 1, width));
+            cases.push((boundary, width + 1));
+        }
+        cases.push((u64::MAX, 8));
+        for (value, width) in cases {
+            let bytes = assemble(&format!("PUSH {value}\nHALT")).unwrap();
+            let opcode = Opcode::from_byte(bytes[0]).unwrap();
+            assert_eq!(opcode.immediate_size(), width, "{value} as {opcode}");
+            assert_eq!(bytes.len(), width + 2);
+
+            let program = parse_bytes_to_instructions(&bytes).unwrap();
+            let mut interpreter = Interpreter::new(program);
+            while !matches!(
+                interpreter.next_instruction().unwrap(),
+                InterpreterEvent::ProgramEnd
+            ) {}
+            assert_eq!(interpreter.debug_get_stack(), [value]);
+        }
+    }
+
+    #[test]
+    fn push_literals() {
+        let parse = |literal: &str| {
+            preprocessor::parse_to_statements(&format!("PUSH {literal}")).map(|s| {
+                match s[0].stage {
+                    preprocessor::Stage1::Push(v) => v,
+                    ref other => panic!("{other:?}"),
+                }
+            })
+        };
+        assert_eq!(parse("18446744073709551615").unwrap(), u64::MAX);
+        assert_eq!(parse("-1").unwrap(), u64::MAX);
+        assert_eq!(parse("-1.5").unwrap(), (-1.5f64).to_bits());
+        assert_eq!(parse("1e3").unwrap(), 1000f64.to_bits());
+        for literal in [
+            "18446744073709551616",
+            "-9223372036854775809",
+            "nan",
+            "inf",
+            "1e400",
+            "12abc",
+        ] {
+            assert!(
+                matches!(
+                    parse(literal),
+                    Err(preprocessor::PreprocessorError::NonParsableParameter(_))
+                ),
+                "{literal}"
+            );
+        }
+    }
+
+    #[test]
+    fn truncated_push_is_rejected() {
+        for width in 1..=8u8 {
+            // Every byte after the push would be a valid HALT, but there is one too few
+            let mut bytes = vec![Opcode::Push0 as u8 + width];
+            bytes.resize(width as usize, Opcode::Halt.into());
+            assert!(parse_bytes_to_instructions(&bytes).is_err());
+        }
+    }
+
+    #[test]
+    fn line_numbers_reach_bytecode() {
+        let source = "PUSH 1\n\n// comment\nINC\n:loop\nGOTO :loop";
+        let s = preprocessor::parse_to_statements(source).unwrap();
+        let s = preprocessor::to_stage2(s).unwrap();
+        let s = preprocessor::to_stage3(s).unwrap();
+        // INC expands to a push and ADD, the GOTO to a push and GOTO
+        let lines: Vec<_> = s.iter().map(|s| s.line).collect();
+        assert_eq!(lines, [1, 4, 4, 5, 6]);
+        assert_eq!(
+            preprocessor::line_addresses(&s),
+            [(0, 1), (2, 4), (5, 5), (6, 6)]
+        );
+    }
+
+    #[test]
+    fn undefined_labels_are_errors() {
+        for jump in ["GOTO", "GOTONZ", "CALL", "SETTRAP"] {
+            let source = format!("PUSH 1\n{jump} :missing\nHALT");
+            let s = preprocessor::parse_to_statements(&source).unwrap();
+            let s = preprocessor::to_stage2(s).unwrap();
+            assert!(
+                matches!(
+                    preprocessor::to_stage3(s),
+                    Err(preprocessor::PreprocessorError::InvalidGoto(label)) if label == ":missing"
+                ),
+                "{jump}"
+            );
+        }
+    }
 }
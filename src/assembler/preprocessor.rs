@@ -124,6 +124,8 @@ impl From<u64> for VarlenBytes {
 pub enum Alias {
     Increment,
     Decrement,
+    /// Copy the second item to the top, same as DUP2
+    Over,
 }
 
 impl Alias {
@@ -131,6 +133,7 @@ impl Alias {
         Some(match input {
             "INC" => Alias::Increment,
             "DEC" => Alias::Decrement,
+            "OVER" => Alias::Over,
             _ => return None,
         })
     }
@@ -146,11 +149,20 @@ impl Alias {
                 statements.push(Stage2::Opcode(Opcode::Swap));
                 statements.push(Stage2::Opcode(Opcode::Sub));
             }
+            Alias::Over => statements.push(Stage2::Opcode(Opcode::Dup2)),
         }
         statements
     }
 }
 
+/// Statement of any stage, with the source line it came from
+#[derive(Debug)]
+pub struct Statement<S> {
+    /// Line number starting at 1
+    pub line: usize,
+    pub stage: S,
+}
+
 #[derive(Debug)]
 pub enum Stage1 {
     /// Empty line, or comment
@@ -159,9 +171,13 @@ pub enum Stage1 {
     Print(String),
     Alias(Alias),
     Push(u64),
+    /// Opcode taking its operand from the stack, given an immediate operand instead
+    OpcodeWithParameter(Opcode, u64),
     GotoLabel(String),
     UnresolvedGoto(String),
     UnresolvedConditionalGoto(String),
+    /// Opcode taking a code address from the stack (CALL, SETTRAP), given a label instead
+    UnresolvedLabelOperand(Opcode, String),
 }
 
 #[derive(Debug)]
@@ -171,6 +187,8 @@ pub enum Stage2 {
     GotoLabel(String),
     UnresolvedGoto(String),
     UnresolvedConditionalGoto(String),
+    /// Opcode taking a code address from the stack (CALL, SETTRAP), given a label instead
+    UnresolvedLabelOperand(Opcode, String),
 }
 #[derive(Debug)]
 pub enum Stage3 {
@@ -178,21 +196,41 @@ pub enum Stage3 {
     Push(VarlenBytes),
     ResolvedGoto(VarlenBytes),
     ResolvedConditionalGoto(VarlenBytes),
+    ResolvedLabelOperand(Opcode, VarlenBytes),
 }
 
 impl Stage2 {
+    /// Push of a constant using the narrowest push opcode that fits it
+    fn push(v: u64) -> Stage2 {
+        if v == 0 {
+            Stage2::Opcode(Opcode::Push0)
+        } else {
+            Stage2::Push(VarlenBytes::from(v).reduce())
+        }
+    }
     fn byte_count(&self) -> usize {
         match self {
             Stage2::Opcode(_) => 1,
             Stage2::Push(b) => b.byte_count() + 1,
             Stage2::UnresolvedGoto(_) => 10, // goto = 1, push8 = 1, push8 bytes = 8
             Stage2::UnresolvedConditionalGoto(_) => 10, // goto = 1, push8 = 1, push8 bytes = 8
+            Stage2::UnresolvedLabelOperand(..) => 10, // opcode = 1, push8 = 1, push8 bytes = 8
             Stage2::GotoLabel(_) => 1,
         }
     }
 }
 
 impl Stage3 {
+    fn byte_count(&self) -> usize {
+        match self {
+            Stage3::Opcode(_) => 1,
+            Stage3::Push(b) => b.byte_count() + 1,
+            // push = 1, push bytes, opcode = 1
+            Stage3::ResolvedGoto(b)
+            | Stage3::ResolvedConditionalGoto(b)
+            | Stage3::ResolvedLabelOperand(_, b) => b.byte_count() + 2,
+        }
+    }
     fn compile(self) -> String {
         let mut out = String::new();
         match self {
@@ -210,6 +248,11 @@ impl Stage3 {
                 out.push('\n');
                 out.push_str(&Opcode::GotoNz.to_string());
             }
+            Stage3::ResolvedLabelOperand(opcode, bytes) => {
+                out.push_str(&bytes.compile());
+                out.push('\n');
+                out.push_str(&opcode.to_string());
+            }
         }
         out
     }
@@ -224,15 +267,34 @@ pub enum PreprocessorError {
     NoParameter,
 }
 
-pub fn compile_statements(statements: Vec<Stage3>) -> Result<String, PreprocessorError> {
+pub fn compile_statements(statements: Vec<Statement<Stage3>>) -> Result<String, PreprocessorError> {
     let mut out = String::new();
     for statement in statements {
-        out.push_str(&statement.compile());
+        out.push_str(&statement.stage.compile());
         out.push('\n');
     }
     Ok(out)
 }
 
+/// Parses a PUSH literal into a word. Negative numbers are stored as two's complement.
+/// Literals with a `.` or an exponent (`3.14`, `-1e9`) are stored as f64 bits, as long
+/// as they are finite. Integers that do not fit in 64 bits are not parsed.
+fn parse_literal(input: &str) -> Option<u64> {
+    if let Ok(v) = input.parse::<u64>() {
+        Some(v)
+    } else if let Ok(v) = input.parse::<i64>() {
+        Some(v as u64)
+    } else if input.contains(['.', 'e', 'E']) {
+        input
+            .parse::<f64>()
+            .ok()
+            .filter(|v| v.is_finite())
+            .map(f64::to_bits)
+    } else {
+        None
+    }
+}
+
 fn parse_line(line: &str) -> Result<Stage1, PreprocessorError> {
     let line = line.trim();
     if line.is_empty() {
@@ -256,7 +318,7 @@ fn parse_line(line: &str) -> Result<Stage1, PreprocessorError> {
             // Future feature. allow pushing goto labels directly. This would allow things like callstacks and function returns.
             todo!()
         }
-        let Ok(v) = parameter_str.parse::<u64>() else {
+        let Some(v) = parse_literal(parameter_str) else {
             return Err(PreprocessorError::NonParsableParameter(
                 parameter_str.to_string(),
             ));
@@ -289,6 +351,30 @@ fn parse_line(line: &str) -> Result<Stage1, PreprocessorError> {
                     Stage1::Opcode(Opcode::GotoNz)
                 }
             }
+            Opcode::Call | Opcode::SetTrap => {
+                if let Some(label) = line_iter.next() {
+                    if !label.starts_with(':') {
+                        return Err(PreprocessorError::InvalidGoto(label.to_string()));
+                    }
+                    Stage1::UnresolvedLabelOperand(op, label.to_string())
+                } else {
+                    // Address taken from the stack
+                    Stage1::Opcode(op)
+                }
+            }
+            Opcode::Pick | Opcode::Roll | Opcode::HostCall => match line_iter.clone().next() {
+                Some(parameter_str) if !parameter_str.starts_with("//") => {
+                    line_iter.next();
+                    let Some(v) = parse_literal(parameter_str) else {
+                        return Err(PreprocessorError::NonParsableParameter(
+                            parameter_str.to_string(),
+                        ));
+                    };
+                    Stage1::OpcodeWithParameter(op, v)
+                }
+                // Operand taken from the stack
+                _ => Stage1::Opcode(op),
+            },
             Opcode::Push0
             | Opcode::Push1
             | Opcode::Push2
@@ -309,36 +395,40 @@ fn parse_line(line: &str) -> Result<Stage1, PreprocessorError> {
     Ok(parsed)
 }
 
-pub fn parse_to_statements(input: &str) -> Result<Vec<Stage1>, PreprocessorError> {
+pub fn parse_to_statements(input: &str) -> Result<Vec<Statement<Stage1>>, PreprocessorError> {
     let mut statements = Vec::new();
-    for line in input.lines() {
+    for (line_no, line) in input.lines().enumerate() {
         let s = parse_line(line)?;
-        statements.push(s);
+        statements.push(Statement {
+            line: line_no + 1,
+            stage: s,
+        });
     }
     Ok(statements)
 }
 
-pub fn to_stage2(statements: Vec<Stage1>) -> Result<Vec<Stage2>, PreprocessorError> {
+pub fn to_stage2(
+    statements: Vec<Statement<Stage1>>,
+) -> Result<Vec<Statement<Stage2>>, PreprocessorError> {
     let mut out = Vec::new();
 
     for statement in statements.into_iter() {
+        let line = statement.line;
+        let mut emit = |stage| out.push(Statement { line, stage });
         // Flatten push into specific bit widths
-        match statement {
-            Stage1::Opcode(opcode) => out.push(Stage2::Opcode(opcode)),
+        match statement.stage {
+            Stage1::Opcode(opcode) => emit(Stage2::Opcode(opcode)),
             Stage1::Print(_) => todo!(),
-            Stage1::Alias(alias) => out.extend(alias.compile()),
-            Stage1::Push(v) => {
-                let new = if v == 0 {
-                    Stage2::Opcode(Opcode::Push0)
-                } else {
-                    let b = VarlenBytes::from(v).reduce();
-                    Stage2::Push(b)
-                };
-                out.push(new);
+            Stage1::Alias(alias) => alias.compile().into_iter().for_each(emit),
+            Stage1::Push(v) => emit(Stage2::push(v)),
+            Stage1::OpcodeWithParameter(opcode, v) => {
+                emit(Stage2::push(v));
+                emit(Stage2::Opcode(opcode));
             }
-            Stage1::GotoLabel(s) => out.push(Stage2::GotoLabel(s)),
-            Stage1::UnresolvedGoto(s) => out.push(Stage2::UnresolvedGoto(s)),
-            Stage1::UnresolvedConditionalGoto(s) => out.push(Stage2::UnresolvedConditionalGoto(s)),
+            Stage1::GotoLabel(s) => emit(Stage2::GotoLabel(s)),
+            Stage1::UnresolvedGoto(s) => emit(Stage2::UnresolvedGoto(s)),
+            Stage1::UnresolvedConditionalGoto(s) => emit(Stage2::UnresolvedConditionalGoto(s)),
+            Stage1::UnresolvedLabelOperand(op, s) => emit(Stage2::UnresolvedLabelOperand(op, s)),
             Stage1::Empty => (),
         }
     }
@@ -346,32 +436,59 @@ pub fn to_stage2(statements: Vec<Stage1>) -> Result<Vec<Stage2>, PreprocessorErr
     Ok(out)
 }
 
-pub fn to_stage3(input: Vec<Stage2>) -> Result<Vec<Stage3>, PreprocessorError> {
+/// Bytecode address of the first byte of every source line that assembles to any
+/// bytes, as (address, line number starting at 1)
+pub fn line_addresses(statements: &[Statement<Stage3>]) -> Vec<(u64, usize)> {
+    let mut lines: Vec<(u64, usize)> = Vec::new();
+    let mut byte_count = 0;
+    for s in statements.iter() {
+        // Statements expanded from one line follow each other
+        if lines.last().is_none_or(|(_, line)| *line != s.line) {
+            lines.push((byte_count, s.line));
+        }
+        byte_count += s.stage.byte_count() as u64;
+    }
+    lines
+}
+
+/// Bytecode address of every goto label
+pub fn label_addresses(input: &[Statement<Stage2>]) -> HashMap<String, u64> {
     let mut goto_destinations: HashMap<String, u64> = HashMap::new();
     let mut byte_count = 0;
     for s in input.iter() {
-        if let Stage2::GotoLabel(label) = s {
+        if let Stage2::GotoLabel(label) = &s.stage {
             goto_destinations.insert(label.to_string(), byte_count);
         }
-        byte_count += s.byte_count() as u64;
+        byte_count += s.stage.byte_count() as u64;
     }
+    goto_destinations
+}
+
+pub fn to_stage3(
+    input: Vec<Statement<Stage2>>,
+) -> Result<Vec<Statement<Stage3>>, PreprocessorError> {
+    let goto_destinations = label_addresses(&input);
+    let resolve = |label: String| match goto_destinations.get(&label) {
+        Some(destination) => Ok(*destination),
+        None => Err(PreprocessorError::InvalidGoto(label)),
+    };
     let mut statements = Vec::new();
     for statement in input {
-        let s: Stage3 = match statement {
+        let line = statement.line;
+        let s: Stage3 = match statement.stage {
             Stage2::Opcode(opcode) => Stage3::Opcode(opcode),
             Stage2::Push(b) => Stage3::Push(b),
 
             Stage2::GotoLabel(_) => Stage3::Opcode(Opcode::GotoTarget),
-            Stage2::UnresolvedGoto(label) => {
-                let destination = *goto_destinations.get(&label).expect("TODO");
-                Stage3::ResolvedGoto(destination.into())
-            }
+            Stage2::UnresolvedGoto(label) => Stage3::ResolvedGoto(resolve(label)?.into()),
             Stage2::UnresolvedConditionalGoto(label) => {
-                let destination = *goto_destinations.get(&label).expect("TODO");
-                Stage3::ResolvedConditionalGoto(destination.into())
+                Stage3::ResolvedConditionalGoto(resolve(label)?.into())
+            }
+            Stage2::UnresolvedLabelOperand(op, label) => {
+                Stage3::ResolvedLabelOperand(op, resolve(label)?.into())
             }
         };
-        statements.push(s);
+        statements.push(Statement { line, stage: s });
     }
 
     Ok(statements)
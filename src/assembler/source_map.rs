@@ -0,0 +1,135 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Display;
+
+/// Side-car information linking assembled bytecode back to its source.
+///
+/// Written next to the assembled file as `<file>.map`, one entry per line:
+/// ```text
+/// source prime.xasm
+/// label 10 :is_prime
+/// line 10 12
+/// ```
+/// A `line` entry gives the source line of the bytecode starting at an address, up
+/// to the next `line` entry.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SourceMap {
+    /// Path of the assembly file, as given to the assembler
+    source: Option<String>,
+    /// Goto labels by bytecode address
+    labels: BTreeMap<u64, String>,
+    /// Source line numbers (starting at 1) by bytecode address
+    lines: BTreeMap<u64, usize>,
+}
+
+#[derive(Debug)]
+pub enum SourceMapError {
+    /// Line that is not a known entry, with its line number
+    InvalidEntry(usize),
+}
+
+impl SourceMap {
+    pub fn new(labels: HashMap<String, u64>) -> SourceMap {
+        SourceMap {
+            labels: labels.into_iter().map(|(l, a)| (a, l)).collect(),
+            ..SourceMap::default()
+        }
+    }
+    /// Adds the source file and its (address, line) pairs
+    pub fn with_lines(
+        mut self,
+        source: impl Into<String>,
+        lines: impl IntoIterator<Item = (u64, usize)>,
+    ) -> SourceMap {
+        self.source = Some(source.into());
+        self.lines = lines.into_iter().collect();
+        self
+    }
+    /// Path of the map file for an assembled bytecode file
+    pub fn path_for(bytecode_path: &str) -> String {
+        format!("{bytecode_path}.map")
+    }
+    pub fn parse(input: &str) -> Result<SourceMap, SourceMapError> {
+        let mut map = SourceMap::default();
+        for (line_no, line) in input.lines().enumerate() {
+            let invalid = || SourceMapError::InvalidEntry(line_no + 1);
+            // The source path is the rest of the line, so it can contain spaces
+            if let Some(source) = line.strip_prefix("source ") {
+                map.source = Some(source.to_string());
+                continue;
+            }
+            let mut parts = line.split_whitespace();
+            match (parts.next(), parts.next(), parts.next()) {
+                (None, ..) => (),
+                (Some("label"), Some(addr), Some(label)) => {
+                    let addr = addr.parse().map_err(|_| invalid())?;
+                    map.labels.insert(addr, label.to_string());
+                }
+                (Some("line"), Some(addr), Some(source_line)) => {
+                    let addr = addr.parse().map_err(|_| invalid())?;
+                    let source_line = source_line.parse().map_err(|_| invalid())?;
+                    map.lines.insert(addr, source_line);
+                }
+                _ => return Err(invalid()),
+            }
+        }
+        Ok(map)
+    }
+    pub fn source(&self) -> Option<&str> {
+        self.source.as_deref()
+    }
+    /// Label placed exactly at `addr`
+    pub fn label_at(&self, addr: u64) -> Option<&str> {
+        self.labels.get(&addr).map(String::as_str)
+    }
+    pub fn address_of(&self, label: &str) -> Option<u64> {
+        self.labels
+            .iter()
+            .find(|(_, l)| l.as_str() == label)
+            .map(|(a, _)| *a)
+    }
+    /// Closest label at or before `addr`, with its address
+    pub fn nearest_label(&self, addr: u64) -> Option<(u64, &str)> {
+        self.labels
+            .range(..=addr)
+            .next_back()
+            .map(|(a, l)| (*a, l.as_str()))
+    }
+    /// `:label+offset` description of an address, if there is a label before it
+    pub fn describe(&self, addr: u64) -> Option<String> {
+        self.nearest_label(addr).map(|(a, l)| match addr - a {
+            0 => l.to_string(),
+            offset => format!("{l}+{offset}"),
+        })
+    }
+    /// Source line the bytecode at `addr` was assembled from
+    pub fn line_at(&self, addr: u64) -> Option<usize> {
+        self.lines.range(..=addr).next_back().map(|(_, l)| *l)
+    }
+    /// `file:line` of the source the bytecode at `addr` was assembled from
+    pub fn location(&self, addr: u64) -> Option<String> {
+        let line = self.line_at(addr)?;
+        Some(format!(
+            "{}:{line}",
+            self.source.as_deref().unwrap_or("<unknown>")
+        ))
+    }
+    /// (address, source line) pairs in address order
+    pub fn lines(&self) -> impl Iterator<Item = (u64, usize)> + '_ {
+        self.lines.iter().map(|(a, l)| (*a, *l))
+    }
+}
+
+impl Display for SourceMap {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(source) = &self.source {
+            writeln!(f, "source {source}")?;
+        }
+        for (addr, label) in self.labels.iter() {
+            writeln!(f, "label {addr} {label}")?;
+        }
+        for (addr, line) in self.lines.iter() {
+            writeln!(f, "line {addr} {line}")?;
+        }
+        Ok(())
+    }
+}
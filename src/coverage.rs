@@ -0,0 +1,100 @@
+use std::collections::BTreeMap;
+use std::io::Write;
+
+use crate::assembler::source_map::SourceMap;
+use crate::interpreter::Profile;
+
+/// Executions of each source line in a profiled run
+#[derive(Debug, Clone, PartialEq)]
+pub struct Coverage {
+    /// Hits by line number (starting at 1), only for lines that assembled to bytecode
+    pub lines: BTreeMap<usize, u64>,
+}
+
+impl Coverage {
+    /// A line counts as executed as often as its first instruction was
+    pub fn new(profile: &Profile, source_map: &SourceMap) -> Coverage {
+        let counts = profile.counts();
+        Coverage {
+            lines: source_map
+                .lines()
+                .map(|(addr, line)| (line, counts.get(addr as usize).copied().unwrap_or(0)))
+                .collect(),
+        }
+    }
+    pub fn lines_hit(&self) -> usize {
+        self.lines.values().filter(|hits| **hits > 0).count()
+    }
+
+    /// Writes the coverage as an lcov tracefile for `source_path`
+    pub fn write_lcov(&self, source_path: &str, w: &mut impl Write) -> std::io::Result<()> {
+        writeln!(w, "TN:")?;
+        writeln!(w, "SF:{source_path}")?;
+        for (line, hits) in self.lines.iter() {
+            writeln!(w, "DA:{line},{hits}")?;
+        }
+        writeln!(w, "LF:{}", self.lines.len())?;
+        writeln!(w, "LH:{}", self.lines_hit())?;
+        writeln!(w, "end_of_record")
+    }
+
+    /// `source` with the hits of each line in front of it. Lines that never ran are
+    /// marked `#####`, and lines without any code `-`.
+    pub fn annotate(&self, source: &str) -> String {
+        let mut out = String::new();
+        for (line_no, line) in source.lines().enumerate() {
+            let hits = match self.lines.get(&(line_no + 1)) {
+                Some(0) => "#####".to_string(),
+                Some(hits) => hits.to_string(),
+                None => "-".to_string(),
+            };
+            out.push_str(&format!("{hits:>10} | {line}\n"));
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Coverage;
+    use crate::assembler::assemble_with_map;
+    use crate::interpreter::{Interpreter, InterpreterEvent};
+
+    #[test]
+    fn line_hits() {
+        let source =
+            "PUSH 3\n:loop\n// count down\nDEC\nDUP\nGOTONZ :loop\nGOTO :end\nPUSH 9\n:end\nHALT";
+        let (program, map) = assemble_with_map(source, "count.xasm").unwrap();
+
+        let mut i = Interpreter::new(program);
+        i.start_profiling();
+        while !matches!(i.next_instruction().unwrap(), InterpreterEvent::ProgramEnd) {}
+        let coverage = Coverage::new(i.profile().unwrap(), &map);
+
+        let hits: Vec<_> = coverage.lines.iter().map(|(l, h)| (*l, *h)).collect();
+        assert_eq!(
+            hits,
+            [
+                (1, 1),
+                (2, 3),
+                (4, 3),
+                (5, 3),
+                (6, 3),
+                (7, 1),
+                (8, 0),
+                (9, 1),
+                (10, 1)
+            ]
+        );
+
+        let mut lcov = Vec::new();
+        coverage.write_lcov("count.xasm", &mut lcov).unwrap();
+        let lcov = String::from_utf8(lcov).unwrap();
+        assert!(lcov.starts_with("TN:\nSF:count.xasm\nDA:1,1\n"));
+        assert!(lcov.ends_with("DA:10,1\nLF:9\nLH:8\nend_of_record\n"));
+
+        let listing = coverage.annotate(source);
+        assert!(listing.contains("         - | // count down\n"));
+        assert!(listing.contains("     ##### | PUSH 9\n"));
+    }
+}
@@ -0,0 +1,433 @@
+use std::collections::BTreeSet;
+use std::io::{BufRead, Write};
+use std::ops::Range;
+
+use crate::assembler::source_map::SourceMap;
+use crate::disassembler::disassemble_instruction;
+use crate::interpreter::{Interpreter, InterpreterError, InterpreterEvent, WatchKind};
+
+/// Steps kept for `back` in the debugger
+const HISTORY_STEPS: usize = 100_000;
+const HISTORY_CHECKPOINT_INTERVAL: usize = 10_000;
+
+const HELP: &str = "\
+s, step [n]                 execute n instructions (default 1)
+c, continue                 run until a breakpoint, the end of the program or an error
+back [n]                    undo n instructions (default 1)
+b, break <addr|:label>      add a breakpoint
+d, delete <addr|:label>     remove a breakpoint
+bl                          list breakpoints
+w, watch <range> [r|w|rw]   stop on memory reads, writes or both (default w) of an offset or a..b
+unwatch <range>             remove watchpoints on a range
+wl                          list watchpoints
+st, stack                   show the stack, top last
+m, mem <offset> [count]     show memory words
+set stack <n> <value>       set the nth item from the top of the stack (0 is the top)
+set mem <offset> <value>    set a memory word
+dis [count]                 disassemble from the current instruction
+q, quit                     exit the debugger
+";
+
+/// Why execution stopped when running under the debugger
+#[derive(Debug)]
+pub enum StopReason {
+    /// Reached a breakpoint, the instruction at it has not executed yet
+    Breakpoint(usize),
+    /// Executed a BRK instruction at this address
+    Brk(usize),
+    /// The instruction at `pc` accessed a watched memory word, it has completed
+    Watchpoint {
+        pc: usize,
+        address: u64,
+        old: u64,
+        new: u64,
+    },
+    /// Executed the requested number of steps
+    Stepped,
+    ProgramEnd,
+    OutOfFuel,
+    Error(InterpreterError),
+    /// An earlier error stopped the program, nothing was executed
+    Failed,
+}
+
+/// Failure of a single debugger command
+enum CommandError {
+    Io(std::io::Error),
+    /// Message for the user, the session continues
+    Invalid(String),
+}
+
+impl From<std::io::Error> for CommandError {
+    fn from(value: std::io::Error) -> Self {
+        CommandError::Io(value)
+    }
+}
+
+/// Interactive debugger driving an [`Interpreter`]
+pub struct Debugger {
+    interpreter: Interpreter,
+    source_map: Option<SourceMap>,
+    breakpoints: BTreeSet<usize>,
+    finished: bool,
+    /// Set by an error, execution cannot continue past it until stepping back
+    failed: bool,
+}
+
+impl Debugger {
+    pub fn new(mut interpreter: Interpreter, source_map: Option<SourceMap>) -> Debugger {
+        interpreter.start_recording(HISTORY_STEPS, HISTORY_CHECKPOINT_INTERVAL);
+        Debugger {
+            interpreter,
+            source_map,
+            breakpoints: BTreeSet::new(),
+            finished: false,
+            failed: false,
+        }
+    }
+    pub fn interpreter(&self) -> &Interpreter {
+        &self.interpreter
+    }
+    pub fn interpreter_mut(&mut self) -> &mut Interpreter {
+        &mut self.interpreter
+    }
+    pub fn add_breakpoint(&mut self, addr: usize) {
+        self.breakpoints.insert(addr);
+    }
+    pub fn remove_breakpoint(&mut self, addr: usize) -> bool {
+        self.breakpoints.remove(&addr)
+    }
+
+    /// Resolves `:label` through the source map, or parses a plain address
+    pub fn resolve_address(&self, input: &str) -> Option<usize> {
+        if input.starts_with(':') {
+            self.source_map
+                .as_ref()?
+                .address_of(input)
+                .map(|a| a as usize)
+        } else {
+            input.parse().ok()
+        }
+    }
+
+    fn execute(&mut self) -> Option<StopReason> {
+        if self.failed {
+            return Some(StopReason::Failed);
+        }
+        if self.finished {
+            return Some(StopReason::ProgramEnd);
+        }
+        match self.interpreter.next_instruction() {
+            Ok(InterpreterEvent::ProgramEnd) => {
+                self.finished = true;
+                Some(StopReason::ProgramEnd)
+            }
+            Ok(InterpreterEvent::OutOfFuel) => Some(StopReason::OutOfFuel),
+            Ok(InterpreterEvent::Breakpoint { pc }) => Some(StopReason::Brk(pc)),
+            Ok(InterpreterEvent::Watchpoint {
+                pc,
+                address,
+                old,
+                new,
+            }) => Some(StopReason::Watchpoint {
+                pc,
+                address,
+                old,
+                new,
+            }),
+            Ok(_) => None,
+            Err(e) => {
+                self.failed = true;
+                Some(StopReason::Error(e))
+            }
+        }
+    }
+
+    /// Executes up to `steps` instructions, stopping early at the end of the program or on an error
+    pub fn step(&mut self, steps: usize) -> StopReason {
+        for _ in 0..steps {
+            if let Some(reason) = self.execute() {
+                return reason;
+            }
+        }
+        StopReason::Stepped
+    }
+
+    /// Runs until a breakpoint is reached, the program ends or an error occurs.
+    /// A breakpoint on the current instruction does not stop it from executing.
+    pub fn resume(&mut self) -> StopReason {
+        loop {
+            if let Some(reason) = self.execute() {
+                return reason;
+            }
+            let pc = self.interpreter.program_counter();
+            if self.breakpoints.contains(&pc) {
+                return StopReason::Breakpoint(pc);
+            }
+        }
+    }
+
+    /// Undoes up to `steps` instructions, returning how many were undone
+    pub fn step_back(&mut self, steps: usize) -> usize {
+        let undone = self.interpreter.step_back_n(steps);
+        if undone > 0 {
+            self.finished = false;
+            self.failed = false;
+        }
+        undone
+    }
+
+    /// `addr <label+offset>  INSTRUCTION  (file:line)` for an address
+    pub fn describe(&self, addr: usize) -> String {
+        let instruction = disassemble_instruction(self.interpreter.program(), addr)
+            .map_or_else(|| "<end of program>".to_string(), |(text, _)| text);
+        let source_map = self.source_map.as_ref();
+        let mut description = match source_map.and_then(|m| m.describe(addr as u64)) {
+            Some(label) => format!("{addr:>6} <{label}>  {instruction}"),
+            None => format!("{addr:>6}  {instruction}"),
+        };
+        if let Some(location) = source_map.and_then(|m| m.location(addr as u64)) {
+            description.push_str(&format!("  ({location})"));
+        }
+        description
+    }
+
+    fn report(&self, reason: &StopReason, out: &mut impl Write) -> std::io::Result<()> {
+        match reason {
+            StopReason::Breakpoint(addr) => writeln!(out, "Breakpoint at {addr}")?,
+            StopReason::Brk(addr) => writeln!(out, "BRK at {addr}")?,
+            StopReason::Watchpoint {
+                pc,
+                address,
+                old,
+                new,
+            } => writeln!(out, "Watchpoint at {pc}: mem {address} {old} -> {new}")?,
+            StopReason::Stepped => (),
+            StopReason::ProgramEnd => writeln!(out, "Program ended")?,
+            StopReason::OutOfFuel => writeln!(out, "Out of fuel")?,
+            StopReason::Error(e) => writeln!(out, "Error: {e}")?,
+            StopReason::Failed => {
+                writeln!(out, "Program failed, use back to step before the error")?
+            }
+        }
+        writeln!(out, "{}", self.describe(self.interpreter.program_counter()))
+    }
+
+    /// Reads commands from `input` until it ends or `quit`, writing results to `out`
+    pub fn run_repl(&mut self, input: impl BufRead, mut out: impl Write) -> std::io::Result<()> {
+        writeln!(out, "{}", self.describe(self.interpreter.program_counter()))?;
+        write!(out, "(dbg) ")?;
+        out.flush()?;
+        for line in input.lines() {
+            let line = line?;
+            let words: Vec<&str> = line.split_whitespace().collect();
+            if let ["q" | "quit"] = words.as_slice() {
+                break;
+            }
+            match self.command(&words, &mut out) {
+                Ok(()) => (),
+                Err(CommandError::Invalid(message)) => writeln!(out, "{message}")?,
+                Err(CommandError::Io(e)) => return Err(e),
+            }
+            // Show anything the program printed
+            if self.interpreter.flush_output().is_err() {
+                writeln!(out, "Error flushing program output")?;
+            }
+            write!(out, "(dbg) ")?;
+            out.flush()?;
+        }
+        Ok(())
+    }
+
+    fn command(&mut self, words: &[&str], out: &mut impl Write) -> Result<(), CommandError> {
+        let number = |s: &str| {
+            s.parse::<u64>()
+                .map_err(|_| CommandError::Invalid(format!("Expected a number, found {s}")))
+        };
+        let invalid = |message: String| Err(CommandError::Invalid(message));
+
+        match words {
+            [] => (),
+            ["h" | "help"] => write!(out, "{HELP}")?,
+            ["s" | "step", rest @ ..] => {
+                let steps = match rest {
+                    [n] => number(n)? as usize,
+                    _ => 1,
+                };
+                let reason = self.step(steps);
+                self.report(&reason, out)?;
+            }
+            ["c" | "continue"] => {
+                let reason = self.resume();
+                self.report(&reason, out)?;
+            }
+            ["back", rest @ ..] => {
+                let steps = match rest {
+                    [n] => number(n)? as usize,
+                    _ => 1,
+                };
+                let undone = self.step_back(steps);
+                writeln!(out, "Stepped back {undone} instructions")?;
+                self.report(&StopReason::Stepped, out)?;
+            }
+            ["b" | "break", addr] => {
+                let Some(addr) = self.resolve_address(addr) else {
+                    return invalid(format!("Unknown address {addr}"));
+                };
+                self.add_breakpoint(addr);
+                writeln!(
+                    out,
+                    "Breakpoint set at {}",
+                    self.describe(addr).trim_start()
+                )?;
+            }
+            ["d" | "delete", addr] => {
+                let Some(addr) = self.resolve_address(addr) else {
+                    return invalid(format!("Unknown address {addr}"));
+                };
+                if !self.remove_breakpoint(addr) {
+                    return invalid(format!("No breakpoint at {addr}"));
+                }
+            }
+            ["bl"] => {
+                for addr in self.breakpoints.iter() {
+                    writeln!(out, "{}", self.describe(*addr))?;
+                }
+            }
+            ["w" | "watch", range, rest @ ..] => {
+                let kind = match rest {
+                    [] | ["w"] => WatchKind::Write,
+                    ["r"] => WatchKind::Read,
+                    ["rw"] => WatchKind::ReadWrite,
+                    _ => return invalid(format!("Unknown watch kind {}", rest.join(" "))),
+                };
+                let range = parse_range(range)?;
+                writeln!(out, "Watching {range:?} for {kind:?}")?;
+                self.interpreter.add_watchpoint(range, kind);
+            }
+            ["unwatch", range] => {
+                let range = parse_range(range)?;
+                if !self.interpreter.remove_watchpoint(&range) {
+                    return invalid(format!("No watchpoint on {range:?}"));
+                }
+            }
+            ["wl"] => {
+                for w in self.interpreter.watchpoints() {
+                    writeln!(out, "{:?} {:?}", w.offsets, w.kind)?;
+                }
+            }
+            ["st" | "stack"] => writeln!(out, "{:?}", self.interpreter.debug_get_stack())?,
+            ["m" | "mem", offset, rest @ ..] => {
+                let offset = number(offset)? as usize;
+                let count = match rest {
+                    [n] => number(n)? as usize,
+                    _ => 1,
+                };
+                let memory = self.interpreter.debug_get_memory();
+                let end = offset.saturating_add(count).min(memory.len());
+                if offset >= end {
+                    return invalid(format!("Memory offset {offset} out of range"));
+                }
+                for (i, v) in memory[offset..end].iter().enumerate() {
+                    writeln!(out, "{:>6}: {v}", offset + i)?;
+                }
+            }
+            ["set", "stack", n, value] => {
+                let (n, value) = (number(n)?, number(value)?);
+                if self.interpreter.debug_set_stack(n, value).is_err() {
+                    return invalid(format!("No stack item {n}"));
+                }
+            }
+            ["set", "mem", offset, value] => {
+                let (offset, value) = (number(offset)?, number(value)?);
+                if self.interpreter.debug_set_memory(offset, value).is_err() {
+                    return invalid(format!("Memory offset {offset} out of range"));
+                }
+            }
+            ["dis", rest @ ..] => {
+                let count = match rest {
+                    [n] => number(n)? as usize,
+                    _ => 10,
+                };
+                let mut addr = self.interpreter.program_counter();
+                for _ in 0..count {
+                    let Some((_, len)) = disassemble_instruction(self.interpreter.program(), addr)
+                    else {
+                        break;
+                    };
+                    writeln!(out, "{}", self.describe(addr))?;
+                    addr += len;
+                }
+            }
+            _ => return invalid(format!("Unknown command {}, try help", words.join(" "))),
+        }
+        Ok(())
+    }
+}
+
+/// Parses `offset` or `start..end` into a range of memory offsets
+fn parse_range(input: &str) -> Result<Range<u64>, CommandError> {
+    let number = |s: &str| s.parse::<u64>().ok();
+    let range = match input.split_once("..") {
+        Some((start, end)) => number(start).zip(number(end)).map(|(s, e)| s..e),
+        None => number(input).and_then(|o| Some(o..o.checked_add(1)?)),
+    };
+    match range {
+        Some(range) if !range.is_empty() => Ok(range),
+        _ => Err(CommandError::Invalid(format!(
+            "Invalid memory range {input}"
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{parse_range, Debugger};
+    use crate::assembler::{assemble, assemble_with_map};
+    use crate::interpreter::Interpreter;
+
+    #[test]
+    fn scripted_session() {
+        let source = "PUSH 2\nCALL :double\nHALT\n:double\nDUP\nADD\nRET";
+        let (program, map) = assemble_with_map(source, "double.xasm").unwrap();
+
+        let mut debugger = Debugger::new(Interpreter::new(program), Some(map));
+        let script = "b :double\nc\ns 2\nst\nset stack 0 5\nset mem 3 7\nm 3\nc\nst\n";
+        let mut out = Vec::new();
+        debugger.run_repl(script.as_bytes(), &mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+
+        assert!(out.contains("Breakpoint at 13\n    13 <:double>  GOTOTARGET"));
+        assert!(out.contains("15 <:double+2>  ADD"));
+        assert!(out.contains("[2, 2]"));
+        assert!(out.contains("     3: 7"));
+        assert!(out.contains("Program ended"));
+        assert_eq!(debugger.interpreter().debug_get_stack(), [7]);
+    }
+
+    #[test]
+    fn stops_after_error() {
+        let program = assemble("PUSH 0\nPUSH 1\nDIV\nHALT").unwrap();
+        let mut debugger = Debugger::new(Interpreter::new(program), None);
+        let script = "c\ns\nc\nst\nback\nst\nset stack 0 6\nset stack 1 2\nc\nst\n";
+        let mut out = Vec::new();
+        debugger.run_repl(script.as_bytes(), &mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+
+        assert!(out.contains("Error: "));
+        assert_eq!(out.matches("Program failed, use back").count(), 2);
+        assert!(out.contains("Stepped back 1 instructions"));
+        assert!(out.contains("[0, 1]"));
+        assert!(out.contains("Program ended"));
+        assert_eq!(debugger.interpreter().debug_get_stack(), [3]);
+    }
+
+    #[test]
+    fn watch_ranges() {
+        assert!(matches!(parse_range("3"), Ok(r) if r == (3..4)));
+        assert!(matches!(parse_range("2..6"), Ok(r) if r == (2..6)));
+        for invalid in ["5..5", "x", "18446744073709551615"] {
+            assert!(parse_range(invalid).is_err(), "{invalid}");
+        }
+    }
+}
@@ -0,0 +1,30 @@
+use crate::parser::{decode_instruction, DecodedInstruction};
+
+/// Decodes the instruction at `addr`, returning its mnemonic with any immediate
+/// parameter and its length in bytes. Unknown opcodes and truncated parameters are
+/// shown as raw bytes.
+pub fn disassemble_instruction(program: &[u8], addr: usize) -> Option<(String, usize)> {
+    match decode_instruction(program, addr)? {
+        Ok(DecodedInstruction {
+            opcode: Some(opcode),
+            immediate,
+            len,
+            ..
+        }) => match immediate {
+            Some(value) => Some((format!("{opcode} {value}"), len)),
+            None => Some((opcode.to_string(), len)),
+        },
+        _ => Some((format!("DB {}", program[addr]), 1)),
+    }
+}
+
+/// Decodes a whole program into (address, instruction) pairs
+pub fn disassemble(program: &[u8]) -> Vec<(usize, String)> {
+    let mut out = Vec::new();
+    let mut addr = 0;
+    while let Some((text, len)) = disassemble_instruction(program, addr) {
+        out.push((addr, text));
+        addr += len;
+    }
+    out
+}
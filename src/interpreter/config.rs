@@ -0,0 +1,113 @@
+use super::Interpreter;
+use crate::opcode::Opcode;
+
+pub const DEFAULT_STACK_SIZE: usize = 64;
+pub const DEFAULT_RETURN_STACK_SIZE: usize = 256;
+pub const DEFAULT_MEMORY_SIZE: usize = 8192;
+
+/// Fuel charged for each opcode, indexed by opcode byte
+pub type CostTable = [u64; 256];
+
+/// Every instruction costs 1, except those doing noticeably more work than a stack
+/// shuffle: division, square roots, sub-word memory access and leaving the VM.
+pub fn default_cost_table() -> CostTable {
+    use Opcode::*;
+    let mut table = [1; 256];
+    for opcode in [Div, Mod, SDiv, SMod, FDiv] {
+        table[opcode as usize] = 4;
+    }
+    table[FSqrt as usize] = 8;
+    for opcode in [
+        MemLoad8, MemLoad16, MemLoad32, MemLoad8S, MemLoad16S, MemLoad32S,
+    ] {
+        table[opcode as usize] = 2;
+    }
+    for opcode in [MemStore8, MemStore16, MemStore32] {
+        table[opcode as usize] = 2;
+    }
+    for opcode in [HostCall, ReadChar, ReadNum, Debug, DebugChar, DebugFloat] {
+        table[opcode as usize] = 16;
+    }
+    table
+}
+
+/// Per instance settings for an [`Interpreter`].
+///
+/// ```
+/// use stack_machine::interpreter::InterpreterConfig;
+///
+/// let interpreter = InterpreterConfig::new()
+///     .stack_size(1024)
+///     .memory_size(1 << 20)
+///     .build(vec![7]);
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct InterpreterConfig {
+    /// Maximum number of words on the stack
+    pub stack_size: usize,
+    /// Maximum number of nested CALLs
+    pub return_stack_size: usize,
+    /// Number of words of memory addressable by MLOAD/MSTORE
+    pub memory_size: usize,
+    /// Fuel available at the start, `None` runs without metering
+    pub fuel: Option<u64>,
+    /// Fuel charged per opcode when metering
+    pub cost_table: CostTable,
+    /// Whether BRK stops with [`InterpreterEvent::Breakpoint`](super::InterpreterEvent::Breakpoint),
+    /// otherwise it is a no-op
+    pub brk_enabled: bool,
+    /// Whether to verify the program when it is loaded, and run it without stack and
+    /// jump checks if it is proven safe. Programs that are not run fully checked.
+    pub fast_path: bool,
+}
+
+impl Default for InterpreterConfig {
+    fn default() -> Self {
+        InterpreterConfig {
+            stack_size: DEFAULT_STACK_SIZE,
+            return_stack_size: DEFAULT_RETURN_STACK_SIZE,
+            memory_size: DEFAULT_MEMORY_SIZE,
+            fuel: None,
+            cost_table: default_cost_table(),
+            brk_enabled: true,
+            fast_path: false,
+        }
+    }
+}
+
+impl InterpreterConfig {
+    pub fn new() -> InterpreterConfig {
+        InterpreterConfig::default()
+    }
+    pub fn stack_size(mut self, stack_size: usize) -> InterpreterConfig {
+        self.stack_size = stack_size;
+        self
+    }
+    pub fn return_stack_size(mut self, return_stack_size: usize) -> InterpreterConfig {
+        self.return_stack_size = return_stack_size;
+        self
+    }
+    pub fn memory_size(mut self, memory_size: usize) -> InterpreterConfig {
+        self.memory_size = memory_size;
+        self
+    }
+    pub fn fuel(mut self, fuel: u64) -> InterpreterConfig {
+        self.fuel = Some(fuel);
+        self
+    }
+    pub fn cost_table(mut self, cost_table: CostTable) -> InterpreterConfig {
+        self.cost_table = cost_table;
+        self
+    }
+    pub fn brk_enabled(mut self, brk_enabled: bool) -> InterpreterConfig {
+        self.brk_enabled = brk_enabled;
+        self
+    }
+    pub fn fast_path(mut self, fast_path: bool) -> InterpreterConfig {
+        self.fast_path = fast_path;
+        self
+    }
+    pub fn build(self, program: Vec<u8>) -> Interpreter {
+        Interpreter::with_config(program, self)
+    }
+}
@@ -0,0 +1,127 @@
+use std::fmt::Display;
+
+use crate::opcode::Opcode;
+
+/// Stack items kept in an [`InterpreterError`]
+const STACK_CONTEXT: usize = 8;
+
+/// What went wrong, with the operand that caused it where there is one
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
+    /// Jump or call to an address that is not a GOTOTARGET
+    InvalidGoto(u64),
+    /// Push too many items on stack
+    StackOverflow,
+    /// Popped too many items from stack
+    StackUnderflow,
+    /// Too many nested calls
+    ReturnStackOverflow,
+    /// Returned without a matching call
+    ReturnStackUnderflow,
+
+    DivideByZero,
+
+    /// Memory word offset out of range, or the byte address of a sub-word access that
+    /// is misaligned or out of range
+    InvalidMemoryOffset(u64),
+    InvalidInstruction,
+    /// HOSTCALL with an id that has no registered function
+    UnknownHostFunction(u64),
+    /// Reading input failed, or READNUM read a line that is not a decimal number below
+    /// u64::MAX
+    InvalidInput,
+    /// Writing to the output sink failed
+    OutputError,
+}
+
+impl ErrorKind {
+    /// Code pushed for the error when it is caught by a trap handler
+    pub fn code(&self) -> u64 {
+        use ErrorKind::*;
+        match self {
+            InvalidGoto(_) => 1,
+            StackOverflow => 2,
+            StackUnderflow => 3,
+            ReturnStackOverflow => 4,
+            ReturnStackUnderflow => 5,
+            DivideByZero => 6,
+            InvalidMemoryOffset(_) => 7,
+            InvalidInstruction => 8,
+            UnknownHostFunction(_) => 9,
+            InvalidInput => 10,
+            OutputError => 11,
+        }
+    }
+    /// Address, offset or id that caused the error
+    pub fn operand(&self) -> Option<u64> {
+        match self {
+            ErrorKind::InvalidGoto(v)
+            | ErrorKind::InvalidMemoryOffset(v)
+            | ErrorKind::UnknownHostFunction(v) => Some(*v),
+            _ => None,
+        }
+    }
+}
+
+impl Display for ErrorKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        use ErrorKind::*;
+        match self {
+            InvalidGoto(addr) => write!(f, "address {addr} is not a GOTOTARGET"),
+            StackOverflow => write!(f, "stack overflow"),
+            StackUnderflow => write!(f, "stack underflow"),
+            ReturnStackOverflow => write!(f, "return stack overflow"),
+            ReturnStackUnderflow => write!(f, "return without a matching call"),
+            DivideByZero => write!(f, "division by zero"),
+            InvalidMemoryOffset(offset) => write!(f, "invalid memory offset {offset}"),
+            InvalidInstruction => write!(f, "invalid instruction"),
+            UnknownHostFunction(id) => write!(f, "no host function registered with id {id}"),
+            InvalidInput => write!(f, "invalid input"),
+            OutputError => write!(f, "error writing output"),
+        }
+    }
+}
+
+impl std::error::Error for ErrorKind {}
+
+/// Error that stopped execution, with where it happened
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InterpreterError {
+    pub kind: ErrorKind,
+    /// Address of the instruction that failed
+    pub pc: usize,
+    /// Byte of the instruction that failed
+    pub opcode: u8,
+    /// Top of the stack just before the failing instruction ran, so including the
+    /// operands it popped. The top of the stack is the last item.
+    pub stack: Vec<u64>,
+}
+
+impl InterpreterError {
+    pub(super) fn new(kind: ErrorKind, pc: usize, opcode: u8, stack: &[u64]) -> InterpreterError {
+        InterpreterError {
+            kind,
+            pc,
+            opcode,
+            stack: stack[stack.len().saturating_sub(STACK_CONTEXT)..].to_vec(),
+        }
+    }
+    pub fn code(&self) -> u64 {
+        self.kind.code()
+    }
+    pub fn operand(&self) -> Option<u64> {
+        self.kind.operand()
+    }
+}
+
+impl Display for InterpreterError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match Opcode::from_byte(self.opcode) {
+            Some(opcode) => write!(f, "{opcode}")?,
+            None => write!(f, "DB {}", self.opcode)?,
+        }
+        write!(f, " at {}: {}, stack {:?}", self.pc, self.kind, self.stack)
+    }
+}
+
+impl std::error::Error for InterpreterError {}
@@ -0,0 +1,272 @@
+//! Execution recording for stepping backwards.
+//!
+//! While recording, every executed instruction logs an undo delta: the registers
+//! before it ran, the stack slots it could have overwritten and the old value of
+//! every memory word it wrote. Deltas are grouped into segments that each start
+//! with a full checkpoint of the machine, so history is bounded by dropping the
+//! oldest segment, and long jumps backwards can restore a checkpoint instead of
+//! undoing every step. The first step of a segment is always undone from its
+//! checkpoint, so HOSTCALL, whose host function can change anything, starts a new
+//! segment rather than logging the whole machine in its delta.
+//!
+//! Input read and output written by the program, and side effects of host
+//! functions outside the machine, are not undone.
+
+use std::collections::VecDeque;
+
+use super::Interpreter;
+use crate::opcode::Opcode;
+
+/// Stack slots saved for instructions with a fixed stack effect. No such
+/// instruction reaches further down than this.
+const FIXED_STACK_WINDOW: usize = 8;
+
+/// Full machine state at the start of a segment
+#[derive(Debug)]
+struct Checkpoint {
+    program_counter: usize,
+    stack: Vec<u64>,
+    return_stack: Vec<usize>,
+    memory: Box<[u64]>,
+    trap_handler: Option<usize>,
+    fuel: Option<u64>,
+    silent: bool,
+}
+
+/// Undo information for one executed instruction
+#[derive(Debug)]
+struct Delta {
+    program_counter: usize,
+    stack_length: usize,
+    return_stack_length: usize,
+    /// Top of the return stack, which RET leaves in place but a later CALL overwrites
+    return_stack_top: usize,
+    trap_handler: Option<usize>,
+    fuel: Option<u64>,
+    silent: bool,
+    /// Index of the first saved stack slot
+    stack_base: usize,
+    /// Range of this delta's saved slots in `Segment::stack_values`
+    stack_values_start: usize,
+    /// Start of this delta's writes in `Segment::memory_writes`
+    memory_writes_start: usize,
+}
+
+#[derive(Debug)]
+struct Segment {
+    checkpoint: Checkpoint,
+    deltas: Vec<Delta>,
+    stack_values: Vec<u64>,
+    /// (offset, old value) of memory writes, in the order they happened
+    memory_writes: Vec<(usize, u64)>,
+}
+
+#[derive(Debug)]
+pub(super) struct History {
+    segments: VecDeque<Segment>,
+    max_steps: usize,
+    checkpoint_interval: usize,
+    steps: usize,
+}
+
+impl History {
+    /// Logs the old value of a memory word about to be written by the current instruction
+    pub(super) fn log_memory_write(&mut self, offset: usize, old: u64) {
+        if let Some(segment) = self.segments.back_mut() {
+            segment.memory_writes.push((offset, old));
+        }
+    }
+}
+
+impl Interpreter {
+    /// Starts recording execution so it can be walked backwards with
+    /// [`Interpreter::step_back`]. At most `max_steps` steps are kept (rounded up to a
+    /// whole segment), with a full checkpoint of the machine every `checkpoint_interval`
+    /// steps. Any previous recording is discarded.
+    pub fn start_recording(&mut self, max_steps: usize, checkpoint_interval: usize) {
+        self.history = Some(History {
+            segments: VecDeque::new(),
+            max_steps,
+            checkpoint_interval: checkpoint_interval.max(1),
+            steps: 0,
+        });
+        self.update_instrumented();
+    }
+    pub fn stop_recording(&mut self) {
+        self.history = None;
+        self.update_instrumented();
+    }
+    /// Number of steps that can currently be undone
+    pub fn recorded_steps(&self) -> usize {
+        self.history.as_ref().map_or(0, |h| h.steps)
+    }
+
+    fn checkpoint(&self) -> Checkpoint {
+        Checkpoint {
+            program_counter: self.program_counter,
+            stack: self.debug_get_stack().to_vec(),
+            return_stack: self.return_stack[..self.return_stack_length].to_vec(),
+            memory: self.memory.clone(),
+            trap_handler: self.trap_handler,
+            fuel: self.fuel,
+            silent: self.silent,
+        }
+    }
+    fn restore_checkpoint(&mut self, checkpoint: &Checkpoint) {
+        self.program_counter = checkpoint.program_counter;
+        self.stack_length = checkpoint.stack.len();
+        self.stack[..self.stack_length].copy_from_slice(&checkpoint.stack);
+        self.return_stack_length = checkpoint.return_stack.len();
+        self.return_stack[..self.return_stack_length].copy_from_slice(&checkpoint.return_stack);
+        self.memory.copy_from_slice(&checkpoint.memory);
+        self.trap_handler = checkpoint.trap_handler;
+        self.fuel = checkpoint.fuel;
+        self.silent = checkpoint.silent;
+    }
+
+    /// Records the undo delta for `instr`, called before it executes with the
+    /// program counter still on it and its fuel already charged.
+    pub(super) fn record_step(&mut self, instr: u8) {
+        let window = match Opcode::from_byte(instr) {
+            // Reach depends on the operand on top of the stack
+            Some(Opcode::Pick | Opcode::Roll) => self
+                .get_nth_from_top(0)
+                .map_or(0, |n| (n as usize).saturating_add(2)),
+            _ => FIXED_STACK_WINDOW,
+        };
+        let stack_base = self.stack_length - window.min(self.stack_length);
+        let fuel = self
+            .fuel
+            .map(|f| f + self.config.cost_table[instr as usize]);
+        let needs_checkpoint = self.history.as_ref().is_some_and(|h| {
+            instr == Opcode::HostCall as u8
+                || h.segments
+                    .back()
+                    .is_none_or(|s| s.deltas.len() >= h.checkpoint_interval)
+        });
+        let checkpoint = needs_checkpoint.then(|| {
+            let mut checkpoint = self.checkpoint();
+            checkpoint.fuel = fuel;
+            checkpoint
+        });
+
+        let Some(history) = self.history.as_mut() else {
+            return;
+        };
+        if let Some(checkpoint) = checkpoint {
+            history.segments.push_back(Segment {
+                checkpoint,
+                deltas: Vec::new(),
+                stack_values: Vec::new(),
+                memory_writes: Vec::new(),
+            });
+            // Drop the oldest segment once the rest still covers `max_steps`
+            while history.segments.len() > 1
+                && history.steps - history.segments[0].deltas.len() >= history.max_steps
+            {
+                let dropped = history.segments.pop_front().unwrap();
+                history.steps -= dropped.deltas.len();
+            }
+        }
+        let segment = history.segments.back_mut().unwrap();
+        segment.deltas.push(Delta {
+            program_counter: self.program_counter,
+            stack_length: self.stack_length,
+            return_stack_length: self.return_stack_length,
+            return_stack_top: self
+                .return_stack_length
+                .checked_sub(1)
+                .map_or(0, |top| self.return_stack[top]),
+            trap_handler: self.trap_handler,
+            fuel,
+            silent: self.silent,
+            stack_base,
+            stack_values_start: segment.stack_values.len(),
+            memory_writes_start: segment.memory_writes.len(),
+        });
+        segment
+            .stack_values
+            .extend_from_slice(&self.stack[stack_base..self.stack_length]);
+        history.steps += 1;
+    }
+
+    /// Undoes the last executed instruction. Returns false if there is no recorded
+    /// history left to undo.
+    pub fn step_back(&mut self) -> bool {
+        let Some(history) = self.history.as_mut() else {
+            return false;
+        };
+        let Some(segment) = history.segments.back_mut() else {
+            return false;
+        };
+        let Some(delta) = segment.deltas.pop() else {
+            history.segments.pop_back();
+            return self.step_back();
+        };
+        history.steps -= 1;
+        if segment.deltas.is_empty() {
+            let segment = history.segments.pop_back().unwrap();
+            self.restore_checkpoint(&segment.checkpoint);
+            return true;
+        }
+
+        for (offset, old) in segment
+            .memory_writes
+            .drain(delta.memory_writes_start..)
+            .rev()
+        {
+            self.memory[offset] = old;
+        }
+        let saved = segment.stack_values.drain(delta.stack_values_start..);
+        self.stack[delta.stack_base..delta.stack_length].copy_from_slice(saved.as_slice());
+        drop(saved);
+
+        self.program_counter = delta.program_counter;
+        self.stack_length = delta.stack_length;
+        self.return_stack_length = delta.return_stack_length;
+        if let Some(top) = delta.return_stack_length.checked_sub(1) {
+            self.return_stack[top] = delta.return_stack_top;
+        }
+        self.trap_handler = delta.trap_handler;
+        self.fuel = delta.fuel;
+        self.silent = delta.silent;
+        true
+    }
+
+    /// Undoes up to `steps` instructions, returning how many were undone. Whole
+    /// segments are skipped by restoring checkpoints.
+    pub fn step_back_n(&mut self, steps: usize) -> usize {
+        let mut undone = 0;
+        while undone < steps {
+            let Some(history) = self.history.as_mut() else {
+                break;
+            };
+            let Some(segment) = history.segments.back() else {
+                break;
+            };
+            if steps - undone >= segment.deltas.len() && !segment.deltas.is_empty() {
+                let segment = history.segments.pop_back().unwrap();
+                history.steps -= segment.deltas.len();
+                undone += segment.deltas.len();
+                self.restore_checkpoint(&segment.checkpoint);
+            } else if self.step_back() {
+                undone += 1;
+            } else {
+                break;
+            }
+        }
+        undone
+    }
+
+    /// Steps backwards until the program counter is at `pc`, that is until just
+    /// before the most recent execution of the instruction at `pc`. Returns false,
+    /// with all recorded history undone, if it was not executed in the recording.
+    pub fn run_back_to(&mut self, pc: usize) -> bool {
+        while self.step_back() {
+            if self.program_counter == pc {
+                return true;
+            }
+        }
+        false
+    }
+}
@@ -0,0 +1,43 @@
+use std::collections::HashMap;
+
+use super::{ErrorKind, Interpreter};
+
+/// Rust function callable from bytecode through HOSTCALL
+pub type HostFn = Box<dyn FnMut(&mut HostContext) -> Result<(), ErrorKind>>;
+
+/// Registered host functions by id
+#[derive(Default)]
+pub(super) struct HostFunctions(pub(super) HashMap<u64, HostFn>);
+
+impl std::fmt::Debug for HostFunctions {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_set().entries(self.0.keys()).finish()
+    }
+}
+
+/// View of the machine handed to a host function while it runs
+pub struct HostContext<'a> {
+    pub(super) interpreter: &'a mut Interpreter,
+}
+
+impl HostContext<'_> {
+    pub fn push(&mut self, value: u64) -> Result<(), ErrorKind> {
+        self.interpreter.push(value)
+    }
+    pub fn pop(&mut self) -> Result<u64, ErrorKind> {
+        self.interpreter.pop()
+    }
+    /// Stack contents, the top of the stack is the last item
+    pub fn stack(&self) -> &[u64] {
+        self.interpreter.debug_get_stack()
+    }
+    pub fn stack_mut(&mut self) -> &mut [u64] {
+        &mut self.interpreter.stack[..self.interpreter.stack_length]
+    }
+    pub fn memory(&self) -> &[u64] {
+        &self.interpreter.memory
+    }
+    pub fn memory_mut(&mut self) -> &mut [u64] {
+        &mut self.interpreter.memory
+    }
+}
@@ -1,6 +1,25 @@
-use super::{Interpreter, InterpreterError, InterpreterEvent};
+use super::{ErrorKind, HostContext, Interpreter, END_OF_INPUT};
+
+/// Why an instruction stopped [`Interpreter::next_instruction`] from just moving on.
+/// Instructions return `Result<(), Interrupt>`, which like `Result<(), ErrorKind>` is
+/// returned in registers, where a value in the `Ok` variant would go through memory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Interrupt {
+    ProgramEnd,
+    /// Toggled silent mode, see [`Interpreter::silent`]
+    Silent,
+    /// Executed an enabled BRK
+    Breakpoint,
+    Error(ErrorKind),
+}
+
+impl From<ErrorKind> for Interrupt {
+    fn from(kind: ErrorKind) -> Self {
+        Interrupt::Error(kind)
+    }
+}
 
-pub type Instruction = fn(&mut Interpreter) -> Result<InterpreterEvent, InterpreterError>;
+pub type Instruction = fn(&mut Interpreter) -> Result<(), Interrupt>;
 
 pub type InstructionTable = [Instruction; 256];
 pub fn get_instruction_table() -> InstructionTable {
@@ -27,13 +46,72 @@ pub fn get_instruction_table() -> InstructionTable {
     table[20] = dup3;
     table[21] = dup4;
     table[22] = noop;
+    table[23] = call;
+    table[24] = ret;
+    table[25] = pick;
+    table[26] = roll;
+    table[27] = rot;
+    table[28] = nip;
+    table[29] = tuck;
+    table[30] = drop2;
+    table[31] = depth;
     table[32] = push0;
     table[33] = push1;
     table[34] = push2;
+    table[35] = push_n::<3>;
+    table[36] = push_n::<4>;
+    table[37] = push_n::<5>;
+    table[38] = push_n::<6>;
+    table[39] = push_n::<7>;
     table[40] = push8;
+    table[41] = set_trap;
+    table[42] = clear_trap;
+    table[48] = sdiv;
+    table[49] = smod;
+    table[50] = slt;
+    table[51] = sgt;
+    table[52] = neg;
+    table[53] = abs;
+    table[54] = sign_extend8;
+    table[55] = sign_extend16;
+    table[56] = sign_extend32;
+    table[64] = and;
+    table[65] = or;
+    table[66] = xor;
+    table[67] = shl;
+    table[68] = shr;
+    table[69] = sar;
+    table[70] = rotl;
+    table[71] = rotr;
+    table[72] = popcnt;
+    table[73] = clz;
+    table[74] = ctz;
+    table[80] = fadd;
+    table[81] = fsub;
+    table[82] = fmul;
+    table[83] = fdiv;
+    table[84] = fsqrt;
+    table[85] = flt;
+    table[86] = feq;
+    table[87] = itof;
+    table[88] = ftoi;
+    table[96] = mem_load_sub::<1>;
+    table[97] = mem_load_sub::<2>;
+    table[98] = mem_load_sub::<4>;
+    table[99] = mem_load_sub_signed::<1>;
+    table[100] = mem_load_sub_signed::<2>;
+    table[101] = mem_load_sub_signed::<4>;
+    table[102] = mem_store_sub::<1>;
+    table[103] = mem_store_sub::<2>;
+    table[104] = mem_store_sub::<4>;
 
     table[128] = noop;
+    table[129] = host_call;
+    table[130] = read_char;
+    table[131] = read_num;
 
+    table[251] = brk;
+    table[252] = debug_float;
     table[253] = debug_silent;
     table[254] = debug_char;
     table[255] = debug;
@@ -41,194 +119,560 @@ pub fn get_instruction_table() -> InstructionTable {
     table
 }
 
-fn debug_silent(_: &mut Interpreter) -> Result<InterpreterEvent, InterpreterError> {
-    Ok(InterpreterEvent::Silent(true))
+fn brk(i: &mut Interpreter) -> Result<(), Interrupt> {
+    if i.config.brk_enabled {
+        Err(Interrupt::Breakpoint)
+    } else {
+        Ok(())
+    }
+}
+
+fn debug_silent(i: &mut Interpreter) -> Result<(), Interrupt> {
+    i.silent = !i.silent;
+    Err(Interrupt::Silent)
+}
+
+fn debug(i: &mut Interpreter) -> Result<(), Interrupt> {
+    let v = i.pop()?;
+    writeln!(i.output.writer(), "{v}").map_err(|_| ErrorKind::OutputError)?;
+    i.push(v)?;
+    Ok(())
 }
 
-fn debug(i: &mut Interpreter) -> Result<InterpreterEvent, InterpreterError> {
+fn debug_float(i: &mut Interpreter) -> Result<(), Interrupt> {
     let v = i.pop()?;
-    println!("{v}");
+    writeln!(i.output.writer(), "{}", f64::from_bits(v)).map_err(|_| ErrorKind::OutputError)?;
     i.push(v)?;
-    Ok(InterpreterEvent::Nothing)
+    Ok(())
 }
 
-fn debug_char(i: &mut Interpreter) -> Result<InterpreterEvent, InterpreterError> {
+fn debug_char(i: &mut Interpreter) -> Result<(), Interrupt> {
     let v = i.pop()?;
     let v: char = char::from_u32((v as u8) as u32).unwrap();
-    print!("{}", v);
-    Ok(InterpreterEvent::Nothing)
+    write!(i.output.writer(), "{}", v).map_err(|_| ErrorKind::OutputError)?;
+    Ok(())
 }
 
-fn push0(i: &mut Interpreter) -> Result<InterpreterEvent, InterpreterError> {
+fn push0(i: &mut Interpreter) -> Result<(), Interrupt> {
     i.push(0)?;
-    Ok(InterpreterEvent::Nothing)
+    Ok(())
 }
 
-fn push1(i: &mut Interpreter) -> Result<InterpreterEvent, InterpreterError> {
+fn push1(i: &mut Interpreter) -> Result<(), Interrupt> {
     let v = i.read_parameter_byte()?;
     i.push(v)?;
-    Ok(InterpreterEvent::Nothing)
+    Ok(())
 }
-fn push2(i: &mut Interpreter) -> Result<InterpreterEvent, InterpreterError> {
+fn push2(i: &mut Interpreter) -> Result<(), Interrupt> {
     let v = i.read_parameter_2byte()?;
     i.push(v)?;
-    Ok(InterpreterEvent::Nothing)
+    Ok(())
 }
 
-pub fn push8(i: &mut Interpreter) -> Result<InterpreterEvent, InterpreterError> {
+/// Push for the odd widths, PUSH3 to PUSH7
+fn push_n<const N: usize>(i: &mut Interpreter) -> Result<(), Interrupt> {
+    let v = i.read_parameter_nbyte::<N>()?;
+    i.push(v)?;
+    Ok(())
+}
+
+pub fn push8(i: &mut Interpreter) -> Result<(), Interrupt> {
     let v = i.read_parameter()?;
     i.push(v)?;
-    Ok(InterpreterEvent::Nothing)
+    Ok(())
 }
 
-pub fn noop(_: &mut Interpreter) -> Result<InterpreterEvent, InterpreterError> {
-    Ok(InterpreterEvent::Nothing)
+pub fn noop(_: &mut Interpreter) -> Result<(), Interrupt> {
+    Ok(())
 }
 
-pub fn goto(i: &mut Interpreter) -> Result<InterpreterEvent, InterpreterError> {
+pub fn host_call(i: &mut Interpreter) -> Result<(), Interrupt> {
+    let id = i.pop()?;
+    // Taken out for the duration of the call so the function can borrow the interpreter
+    let Some(mut f) = i.host_functions.0.remove(&id) else {
+        return Err(ErrorKind::UnknownHostFunction(id).into());
+    };
+    let res = f(&mut HostContext { interpreter: i });
+    i.host_functions.0.insert(id, f);
+    res?;
+    Ok(())
+}
+
+pub fn read_char(i: &mut Interpreter) -> Result<(), Interrupt> {
+    // Make sure any prompt is visible before blocking on input
+    i.flush_output()?;
+    let byte = i.input.read_byte().map_err(|_| ErrorKind::InvalidInput)?;
+    i.push(byte.map_or(END_OF_INPUT, |b| b as u64))?;
+    Ok(())
+}
+
+pub fn read_num(i: &mut Interpreter) -> Result<(), Interrupt> {
+    i.flush_output()?;
+    let line = i.input.read_line().map_err(|_| ErrorKind::InvalidInput)?;
+    let v = match line {
+        Some(line) => line
+            .trim()
+            .parse::<u64>()
+            .ok()
+            .filter(|v| *v != END_OF_INPUT)
+            .ok_or(ErrorKind::InvalidInput)?,
+        None => END_OF_INPUT,
+    };
+    i.push(v)?;
+    Ok(())
+}
+
+pub fn goto(i: &mut Interpreter) -> Result<(), Interrupt> {
     let addr = i.pop()?;
     i.goto(addr)?;
-    Ok(InterpreterEvent::Nothing)
+    Ok(())
 }
 
-pub fn pop(i: &mut Interpreter) -> Result<InterpreterEvent, InterpreterError> {
+pub fn call(i: &mut Interpreter) -> Result<(), Interrupt> {
+    let addr = i.pop()?;
+    let target = i.goto_target(addr)?;
+    i.push_return(i.program_counter)?;
+    i.program_counter = target;
+    Ok(())
+}
+
+pub fn ret(i: &mut Interpreter) -> Result<(), Interrupt> {
+    let addr = i.pop_return()?;
+    i.program_counter = addr;
+    Ok(())
+}
+
+pub fn set_trap(i: &mut Interpreter) -> Result<(), Interrupt> {
+    let addr = i.pop()?;
+    i.trap_handler = Some(i.goto_target(addr)?);
+    Ok(())
+}
+
+pub fn clear_trap(i: &mut Interpreter) -> Result<(), Interrupt> {
+    i.trap_handler = None;
+    Ok(())
+}
+
+pub fn pop(i: &mut Interpreter) -> Result<(), Interrupt> {
     i.pop()?;
-    Ok(InterpreterEvent::Nothing)
+    Ok(())
 }
 
-pub fn add(i: &mut Interpreter) -> Result<InterpreterEvent, InterpreterError> {
+pub fn add(i: &mut Interpreter) -> Result<(), Interrupt> {
     let (a, b) = i.pop_two()?;
 
     let c = a.wrapping_add(b);
     i.confident_push(c);
-    Ok(InterpreterEvent::Nothing)
+    Ok(())
 }
 
-pub fn sub(i: &mut Interpreter) -> Result<InterpreterEvent, InterpreterError> {
+pub fn sub(i: &mut Interpreter) -> Result<(), Interrupt> {
     let (a, b) = i.pop_two()?;
 
     let c = a.wrapping_sub(b);
     i.confident_push(c);
-    Ok(InterpreterEvent::Nothing)
+    Ok(())
 }
 
-pub fn mul(i: &mut Interpreter) -> Result<InterpreterEvent, InterpreterError> {
+pub fn mul(i: &mut Interpreter) -> Result<(), Interrupt> {
     let (a, b) = i.pop_two()?;
 
     let c = a.wrapping_mul(b);
     i.confident_push(c);
-    Ok(InterpreterEvent::Nothing)
+    Ok(())
 }
 
-pub fn div(i: &mut Interpreter) -> Result<InterpreterEvent, InterpreterError> {
+pub fn div(i: &mut Interpreter) -> Result<(), Interrupt> {
     let (a, b) = i.pop_two()?;
 
     if b == 0 {
-        return Err(InterpreterError::DivideByZero);
+        return Err(ErrorKind::DivideByZero.into());
     }
     let c = a.wrapping_div(b);
     i.confident_push(c);
-    Ok(InterpreterEvent::Nothing)
+    Ok(())
 }
 
-pub fn halt(_: &mut Interpreter) -> Result<InterpreterEvent, InterpreterError> {
-    Ok(InterpreterEvent::ProgramEnd)
+pub fn halt(i: &mut Interpreter) -> Result<(), Interrupt> {
+    i.flush_output()?;
+    Err(Interrupt::ProgramEnd)
 }
 
-pub fn swap(i: &mut Interpreter) -> Result<InterpreterEvent, InterpreterError> {
+pub fn swap(i: &mut Interpreter) -> Result<(), Interrupt> {
     i.swap_nth(1)?;
-    Ok(InterpreterEvent::Nothing)
+    Ok(())
 }
 
-pub fn swap2(i: &mut Interpreter) -> Result<InterpreterEvent, InterpreterError> {
+pub fn swap2(i: &mut Interpreter) -> Result<(), Interrupt> {
     i.swap_nth(2)?;
-    Ok(InterpreterEvent::Nothing)
+    Ok(())
 }
 
-pub fn rem(i: &mut Interpreter) -> Result<InterpreterEvent, InterpreterError> {
+pub fn rem(i: &mut Interpreter) -> Result<(), Interrupt> {
     let (a, b) = i.pop_two()?;
 
     if b == 0 {
-        return Err(InterpreterError::DivideByZero);
+        return Err(ErrorKind::DivideByZero.into());
     }
     let c = a % b;
     i.confident_push(c);
-    Ok(InterpreterEvent::Nothing)
+    Ok(())
 }
 
-pub fn mem_load(i: &mut Interpreter) -> Result<InterpreterEvent, InterpreterError> {
+pub fn mem_load(i: &mut Interpreter) -> Result<(), Interrupt> {
     let index = i.pop()?;
     let val = i.load_memory_offset(index)?;
     i.confident_push(val);
 
-    Ok(InterpreterEvent::Nothing)
+    Ok(())
 }
 
-pub fn mem_store(i: &mut Interpreter) -> Result<InterpreterEvent, InterpreterError> {
+pub fn mem_store(i: &mut Interpreter) -> Result<(), Interrupt> {
     let index = i.pop()?;
     let val = i.pop()?;
     i.set_memory_offset(index, val)?;
-    Ok(InterpreterEvent::Nothing)
+    Ok(())
+}
+
+/// Load `N` bytes from a byte address, zero extended
+fn mem_load_sub<const N: u64>(i: &mut Interpreter) -> Result<(), Interrupt> {
+    let addr = i.pop()?;
+    let val = i.load_memory_bytes(addr, N)?;
+    i.confident_push(val);
+    Ok(())
+}
+
+/// Load `N` bytes from a byte address, sign extended
+fn mem_load_sub_signed<const N: u64>(i: &mut Interpreter) -> Result<(), Interrupt> {
+    let addr = i.pop()?;
+    let val = i.load_memory_bytes(addr, N)?;
+    let unused_bits = 64 - N * 8;
+    let val = ((val << unused_bits) as i64 >> unused_bits) as u64;
+    i.confident_push(val);
+    Ok(())
+}
+
+/// Store the lowest `N` bytes of a value at a byte address
+fn mem_store_sub<const N: u64>(i: &mut Interpreter) -> Result<(), Interrupt> {
+    let addr = i.pop()?;
+    let val = i.pop()?;
+    i.store_memory_bytes(addr, N, val)?;
+    Ok(())
 }
 
-pub fn goto_nz(i: &mut Interpreter) -> Result<InterpreterEvent, InterpreterError> {
+pub fn goto_nz(i: &mut Interpreter) -> Result<(), Interrupt> {
     let addr = i.pop()?;
     let conditional = i.pop()?;
     if conditional != 0 {
         i.goto(addr)?;
     }
-    Ok(InterpreterEvent::Nothing)
+    Ok(())
 }
 
-pub fn eq(i: &mut Interpreter) -> Result<InterpreterEvent, InterpreterError> {
+pub fn eq(i: &mut Interpreter) -> Result<(), Interrupt> {
     let (a, b) = i.pop_two()?;
 
     let c = (a == b) as u64;
     i.confident_push(c);
-    Ok(InterpreterEvent::Nothing)
+    Ok(())
 }
 
-pub fn lt(i: &mut Interpreter) -> Result<InterpreterEvent, InterpreterError> {
+pub fn lt(i: &mut Interpreter) -> Result<(), Interrupt> {
     let (a, b) = i.pop_two()?;
 
     let c = (a < b) as u64;
     i.confident_push(c);
-    Ok(InterpreterEvent::Nothing)
+    Ok(())
 }
 
-pub fn gt(i: &mut Interpreter) -> Result<InterpreterEvent, InterpreterError> {
+pub fn gt(i: &mut Interpreter) -> Result<(), Interrupt> {
     let (a, b) = i.pop_two()?;
     let c = (a > b) as u64;
     i.confident_push(c);
-    Ok(InterpreterEvent::Nothing)
+    Ok(())
 }
 
-pub fn dup(i: &mut Interpreter) -> Result<InterpreterEvent, InterpreterError> {
+pub fn dup(i: &mut Interpreter) -> Result<(), Interrupt> {
     let c = i.get_nth_from_top(0)?;
     i.push(c)?;
-    Ok(InterpreterEvent::Nothing)
+    Ok(())
 }
 
-pub fn dup2(i: &mut Interpreter) -> Result<InterpreterEvent, InterpreterError> {
+pub fn dup2(i: &mut Interpreter) -> Result<(), Interrupt> {
     let c = i.get_nth_from_top(1)?;
     i.push(c)?;
-    Ok(InterpreterEvent::Nothing)
+    Ok(())
 }
 
-pub fn dup3(i: &mut Interpreter) -> Result<InterpreterEvent, InterpreterError> {
+pub fn dup3(i: &mut Interpreter) -> Result<(), Interrupt> {
     let c = i.get_nth_from_top(2)?;
     i.push(c)?;
-    Ok(InterpreterEvent::Nothing)
+    Ok(())
 }
 
-pub fn dup4(i: &mut Interpreter) -> Result<InterpreterEvent, InterpreterError> {
+pub fn dup4(i: &mut Interpreter) -> Result<(), Interrupt> {
     let c = i.get_nth_from_top(3)?;
     i.push(c)?;
-    Ok(InterpreterEvent::Nothing)
+    Ok(())
 }
 
-pub fn not(i: &mut Interpreter) -> Result<InterpreterEvent, InterpreterError> {
+pub fn pick(i: &mut Interpreter) -> Result<(), Interrupt> {
+    let n = i.pop()?;
+    let c = i.get_nth_from_top(n)?;
+    i.confident_push(c);
+    Ok(())
+}
+
+pub fn roll(i: &mut Interpreter) -> Result<(), Interrupt> {
+    let n = i.pop()?;
+    i.roll_nth(n)?;
+    Ok(())
+}
+
+pub fn rot(i: &mut Interpreter) -> Result<(), Interrupt> {
+    i.roll_nth(2)?;
+    Ok(())
+}
+
+pub fn nip(i: &mut Interpreter) -> Result<(), Interrupt> {
+    let top = i.pop()?;
+    i.set_nth_from_top(0, top)?;
+    Ok(())
+}
+
+pub fn tuck(i: &mut Interpreter) -> Result<(), Interrupt> {
+    let (a, b) = i.pop_two()?;
+    i.confident_push(a);
+    i.confident_push(b);
+    i.push(a)?;
+    Ok(())
+}
+
+pub fn drop2(i: &mut Interpreter) -> Result<(), Interrupt> {
+    i.pop_two()?;
+    Ok(())
+}
+
+pub fn depth(i: &mut Interpreter) -> Result<(), Interrupt> {
+    i.push(i.stack_length as u64)?;
+    Ok(())
+}
+
+pub fn not(i: &mut Interpreter) -> Result<(), Interrupt> {
     let a = i.pop()?;
     i.confident_push(!a);
-    Ok(InterpreterEvent::Nothing)
+    Ok(())
+}
+
+pub fn sdiv(i: &mut Interpreter) -> Result<(), Interrupt> {
+    let (a, b) = i.pop_two()?;
+
+    if b == 0 {
+        return Err(ErrorKind::DivideByZero.into());
+    }
+    let c = (a as i64).wrapping_div(b as i64);
+    i.confident_push(c as u64);
+    Ok(())
+}
+
+/// Signed remainder, the result takes the sign of the dividend
+pub fn smod(i: &mut Interpreter) -> Result<(), Interrupt> {
+    let (a, b) = i.pop_two()?;
+
+    if b == 0 {
+        return Err(ErrorKind::DivideByZero.into());
+    }
+    let c = (a as i64).wrapping_rem(b as i64);
+    i.confident_push(c as u64);
+    Ok(())
+}
+
+pub fn slt(i: &mut Interpreter) -> Result<(), Interrupt> {
+    let (a, b) = i.pop_two()?;
+
+    let c = ((a as i64) < (b as i64)) as u64;
+    i.confident_push(c);
+    Ok(())
+}
+
+pub fn sgt(i: &mut Interpreter) -> Result<(), Interrupt> {
+    let (a, b) = i.pop_two()?;
+    let c = ((a as i64) > (b as i64)) as u64;
+    i.confident_push(c);
+    Ok(())
+}
+
+pub fn neg(i: &mut Interpreter) -> Result<(), Interrupt> {
+    let a = i.pop()?;
+    i.confident_push(a.wrapping_neg());
+    Ok(())
+}
+
+/// Absolute value, `i64::MIN` has no positive counterpart and is left unchanged
+pub fn abs(i: &mut Interpreter) -> Result<(), Interrupt> {
+    let a = i.pop()?;
+    i.confident_push((a as i64).wrapping_abs() as u64);
+    Ok(())
+}
+
+pub fn sign_extend8(i: &mut Interpreter) -> Result<(), Interrupt> {
+    let a = i.pop()?;
+    i.confident_push(a as i8 as i64 as u64);
+    Ok(())
+}
+
+pub fn sign_extend16(i: &mut Interpreter) -> Result<(), Interrupt> {
+    let a = i.pop()?;
+    i.confident_push(a as i16 as i64 as u64);
+    Ok(())
+}
+
+pub fn sign_extend32(i: &mut Interpreter) -> Result<(), Interrupt> {
+    let a = i.pop()?;
+    i.confident_push(a as i32 as i64 as u64);
+    Ok(())
+}
+
+pub fn and(i: &mut Interpreter) -> Result<(), Interrupt> {
+    let (a, b) = i.pop_two()?;
+    let c = a & b;
+    i.confident_push(c);
+    Ok(())
+}
+
+pub fn or(i: &mut Interpreter) -> Result<(), Interrupt> {
+    let (a, b) = i.pop_two()?;
+    let c = a | b;
+    i.confident_push(c);
+    Ok(())
+}
+
+pub fn xor(i: &mut Interpreter) -> Result<(), Interrupt> {
+    let (a, b) = i.pop_two()?;
+    let c = a ^ b;
+    i.confident_push(c);
+    Ok(())
+}
+
+/// Shifts `a` left by `b`, a shift count of 64 or more clears the word
+pub fn shl(i: &mut Interpreter) -> Result<(), Interrupt> {
+    let (a, b) = i.pop_two()?;
+    let c = if b < 64 { a << b } else { 0 };
+    i.confident_push(c);
+    Ok(())
+}
+
+/// Shifts `a` right by `b`, a shift count of 64 or more clears the word
+pub fn shr(i: &mut Interpreter) -> Result<(), Interrupt> {
+    let (a, b) = i.pop_two()?;
+    let c = if b < 64 { a >> b } else { 0 };
+    i.confident_push(c);
+    Ok(())
+}
+
+/// Arithmetic shift of `a` right by `b`, a shift count of 64 or more fills the word with the sign bit
+pub fn sar(i: &mut Interpreter) -> Result<(), Interrupt> {
+    let (a, b) = i.pop_two()?;
+    let c = (a as i64).wrapping_shr(b.min(63) as u32) as u64;
+    i.confident_push(c);
+    Ok(())
+}
+
+pub fn rotl(i: &mut Interpreter) -> Result<(), Interrupt> {
+    let (a, b) = i.pop_two()?;
+    let c = a.rotate_left((b % 64) as u32);
+    i.confident_push(c);
+    Ok(())
+}
+
+pub fn rotr(i: &mut Interpreter) -> Result<(), Interrupt> {
+    let (a, b) = i.pop_two()?;
+    let c = a.rotate_right((b % 64) as u32);
+    i.confident_push(c);
+    Ok(())
+}
+
+pub fn popcnt(i: &mut Interpreter) -> Result<(), Interrupt> {
+    let a = i.pop()?;
+    i.confident_push(a.count_ones() as u64);
+    Ok(())
+}
+
+pub fn clz(i: &mut Interpreter) -> Result<(), Interrupt> {
+    let a = i.pop()?;
+    i.confident_push(a.leading_zeros() as u64);
+    Ok(())
+}
+
+pub fn ctz(i: &mut Interpreter) -> Result<(), Interrupt> {
+    let a = i.pop()?;
+    i.confident_push(a.trailing_zeros() as u64);
+    Ok(())
+}
+
+pub fn fadd(i: &mut Interpreter) -> Result<(), Interrupt> {
+    let (a, b) = i.pop_two()?;
+    let (a, b) = (f64::from_bits(a), f64::from_bits(b));
+    let c = (a + b).to_bits();
+    i.confident_push(c);
+    Ok(())
+}
+
+pub fn fsub(i: &mut Interpreter) -> Result<(), Interrupt> {
+    let (a, b) = i.pop_two()?;
+    let (a, b) = (f64::from_bits(a), f64::from_bits(b));
+    let c = (a - b).to_bits();
+    i.confident_push(c);
+    Ok(())
+}
+
+pub fn fmul(i: &mut Interpreter) -> Result<(), Interrupt> {
+    let (a, b) = i.pop_two()?;
+    let (a, b) = (f64::from_bits(a), f64::from_bits(b));
+    let c = (a * b).to_bits();
+    i.confident_push(c);
+    Ok(())
+}
+
+pub fn fdiv(i: &mut Interpreter) -> Result<(), Interrupt> {
+    let (a, b) = i.pop_two()?;
+    let (a, b) = (f64::from_bits(a), f64::from_bits(b));
+    let c = (a / b).to_bits();
+    i.confident_push(c);
+    Ok(())
+}
+
+pub fn fsqrt(i: &mut Interpreter) -> Result<(), Interrupt> {
+    let a = i.pop()?;
+    i.confident_push(f64::from_bits(a).sqrt().to_bits());
+    Ok(())
+}
+
+pub fn flt(i: &mut Interpreter) -> Result<(), Interrupt> {
+    let (a, b) = i.pop_two()?;
+    let (a, b) = (f64::from_bits(a), f64::from_bits(b));
+    let c = (a < b) as u64;
+    i.confident_push(c);
+    Ok(())
+}
+
+pub fn feq(i: &mut Interpreter) -> Result<(), Interrupt> {
+    let (a, b) = i.pop_two()?;
+    let (a, b) = (f64::from_bits(a), f64::from_bits(b));
+    let c = (a == b) as u64;
+    i.confident_push(c);
+    Ok(())
+}
+
+pub fn itof(i: &mut Interpreter) -> Result<(), Interrupt> {
+    let a = i.pop()?;
+    i.confident_push((a as i64 as f64).to_bits());
+    Ok(())
+}
+
+/// Truncates towards zero, out of range values saturate and NaN becomes 0
+pub fn ftoi(i: &mut Interpreter) -> Result<(), Interrupt> {
+    let a = i.pop()?;
+    i.confident_push(f64::from_bits(a) as i64 as u64);
+    Ok(())
 }
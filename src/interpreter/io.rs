@@ -0,0 +1,67 @@
+use std::io::{BufRead, BufReader, BufWriter, Read, Write};
+
+/// Value pushed by READCHAR and READNUM once the input is exhausted. READNUM rejects
+/// it as input, so it cannot be mistaken for a number that was read.
+pub const END_OF_INPUT: u64 = u64::MAX;
+
+/// Input source read by READCHAR and READNUM, stdin unless replaced
+pub(super) struct Input(Box<dyn BufRead>);
+
+impl Default for Input {
+    fn default() -> Self {
+        Input::new(std::io::stdin())
+    }
+}
+
+impl std::fmt::Debug for Input {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Input")
+    }
+}
+
+impl Input {
+    pub(super) fn new(source: impl Read + 'static) -> Input {
+        Input(Box::new(BufReader::new(source)))
+    }
+    /// Next byte, or `None` at the end of input
+    pub(super) fn read_byte(&mut self) -> std::io::Result<Option<u8>> {
+        let buf = self.0.fill_buf()?;
+        let Some(&b) = buf.first() else {
+            return Ok(None);
+        };
+        self.0.consume(1);
+        Ok(Some(b))
+    }
+    /// Next line without its line ending, or `None` at the end of input
+    pub(super) fn read_line(&mut self) -> std::io::Result<Option<String>> {
+        let mut line = String::new();
+        if self.0.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        Ok(Some(line.trim_end_matches(['\n', '\r']).to_string()))
+    }
+}
+
+/// Sink written to by the debug printing instructions, buffered stdout unless replaced
+pub(super) struct Output(Box<dyn Write>);
+
+impl Default for Output {
+    fn default() -> Self {
+        Output(Box::new(BufWriter::new(std::io::stdout())))
+    }
+}
+
+impl std::fmt::Debug for Output {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Output")
+    }
+}
+
+impl Output {
+    pub(super) fn new(sink: impl Write + 'static) -> Output {
+        Output(Box::new(sink))
+    }
+    pub(super) fn writer(&mut self) -> &mut dyn Write {
+        &mut self.0
+    }
+}
@@ -1,12 +1,34 @@
+mod config;
+mod error;
+mod history;
+mod host;
 mod instruction;
-use std::io::Read;
+mod io;
+mod profile;
+mod snapshot;
+mod trace;
+mod verified;
+mod watch;
+use std::io::{Read, Write};
 
-use instruction::{get_instruction_table, InstructionTable};
+pub use config::{default_cost_table, CostTable, InterpreterConfig};
+pub use error::{ErrorKind, InterpreterError};
+use history::History;
+use host::HostFunctions;
+pub use host::{HostContext, HostFn};
+use instruction::{get_instruction_table, InstructionTable, Interrupt};
+pub use io::END_OF_INPUT;
+use io::{Input, Output};
+pub use profile::Profile;
+pub use snapshot::{SnapshotError, SNAPSHOT_VERSION};
+use trace::Trace;
+pub use trace::TraceFilter;
+use verified::get_verified_instruction_table;
+use watch::Watchpoints;
+pub use watch::{WatchKind, Watchpoint};
 
 use crate::opcode::Opcode;
-
-const STACK_SIZE: usize = 64;
-const TMP_MEMORY_SIZE: usize = 8192;
+use crate::parser::verify;
 
 #[derive(Debug)]
 pub enum InterpreterEvent {
@@ -14,45 +36,177 @@ pub enum InterpreterEvent {
     Nothing,
     /// Program has reached end without exception
     ProgramEnd,
+    /// DBGSILENT toggled silent mode, which is now on if true
     Silent(bool),
-}
-
-#[derive(Debug)]
-pub enum InterpreterError {
-    /// Invalid Goto location
-    InvalidGoto,
-    /// Push too many items on stack
-    StackOverflow,
-    /// Popped too many items from stack
-    StackUnderflow,
-
-    DivideByZero,
-
-    InvalidMemoryOffset,
-    InvalidInstruction,
+    /// Not enough fuel left for the next instruction, which has not been executed.
+    /// Execution can resume after [`Interpreter::add_fuel`].
+    OutOfFuel,
+    /// Executed the BRK instruction at `pc`, execution resumes after it
+    Breakpoint { pc: usize },
+    /// The instruction at `pc` accessed a watched memory word at `address`, changing
+    /// it from `old` to `new` (equal for reads). The instruction has completed.
+    Watchpoint {
+        pc: usize,
+        address: u64,
+        old: u64,
+        new: u64,
+    },
 }
 
 #[derive(Debug)]
 pub struct Interpreter {
     program: Vec<u8>,
-    stack: [u64; STACK_SIZE],
+    stack: Box<[u64]>,
     /// Temporary memory storage
-    memory: [u64; TMP_MEMORY_SIZE],
+    memory: Box<[u64]>,
     stack_length: usize,
+    /// Return addresses pushed by CALL, separate from the data stack
+    return_stack: Box<[usize]>,
+    return_stack_length: usize,
     program_counter: usize,
+    /// Address jumped to when an instruction fails, set by SETTRAP
+    trap_handler: Option<usize>,
+    /// Remaining fuel, `None` when not metering
+    fuel: Option<u64>,
+    /// Undo log, `None` when not recording
+    history: Option<History>,
+    /// Trace being written, `None` when not tracing
+    trace: Option<Trace>,
+    /// Execution counts, `None` when not profiling
+    profile: Option<Profile>,
+    /// Toggled by DBGSILENT, the program asks for its execution not to be shown
+    silent: bool,
+    watchpoints: Watchpoints,
+    config: InterpreterConfig,
+    host_functions: HostFunctions,
+    input: Input,
+    output: Output,
+    /// Whether the program was proven safe and runs without stack and jump checks
+    verified: bool,
+    /// Whether fuel, recording, tracing, profiling or watchpoints are on, which need
+    /// the slower [`Interpreter::next_instruction_instrumented`]. Kept up to date by
+    /// [`Interpreter::update_instrumented`] whenever one of them is turned on or off.
+    instrumented: bool,
     instruction_table: InstructionTable,
 }
 
 impl Interpreter {
     pub fn new(program: Vec<u8>) -> Interpreter {
-        Interpreter {
+        Interpreter::with_config(program, InterpreterConfig::default())
+    }
+    pub fn with_config(program: Vec<u8>, config: InterpreterConfig) -> Interpreter {
+        let verified = config.fast_path && verify(&program, config.stack_size).is_safe();
+        let mut interpreter = Interpreter {
             program,
-            stack: [0; STACK_SIZE],
-            memory: [0; TMP_MEMORY_SIZE],
+            stack: vec![0; config.stack_size].into_boxed_slice(),
+            memory: vec![0; config.memory_size].into_boxed_slice(),
             stack_length: 0,
+            return_stack: vec![0; config.return_stack_size].into_boxed_slice(),
+            return_stack_length: 0,
             program_counter: 0,
-            instruction_table: get_instruction_table(),
-        }
+            trap_handler: None,
+            fuel: config.fuel,
+            history: None,
+            trace: None,
+            profile: None,
+            silent: false,
+            watchpoints: Watchpoints::default(),
+            config,
+            host_functions: HostFunctions::default(),
+            input: Input::default(),
+            output: Output::default(),
+            verified,
+            instruction_table: if verified {
+                get_verified_instruction_table()
+            } else {
+                get_instruction_table()
+            },
+            instrumented: false,
+        };
+        interpreter.update_instrumented();
+        interpreter
+    }
+    pub fn config(&self) -> &InterpreterConfig {
+        &self.config
+    }
+    /// Whether the program runs on the fast path, see [`InterpreterConfig::fast_path`]
+    pub fn is_verified(&self) -> bool {
+        self.verified
+    }
+    /// Registers a function for HOSTCALL to call with `id`, replacing any previous one.
+    ///
+    /// The function gets access to the stack and memory, and any error it returns is
+    /// raised as if it came from the HOSTCALL instruction.
+    pub fn register_host_fn<F>(&mut self, id: u64, f: F)
+    where
+        F: FnMut(&mut HostContext) -> Result<(), ErrorKind> + 'static,
+    {
+        self.host_functions.0.insert(id, Box::new(f));
+    }
+    /// Remaining fuel, `None` when running without metering
+    pub fn fuel(&self) -> Option<u64> {
+        self.fuel
+    }
+    /// Sets the remaining fuel, `None` turns metering off
+    pub fn set_fuel(&mut self, fuel: Option<u64>) {
+        self.fuel = fuel;
+        self.update_instrumented();
+    }
+    /// Tops up the remaining fuel, turning metering on if it was off
+    pub fn add_fuel(&mut self, amount: u64) {
+        self.fuel = Some(self.fuel.unwrap_or(0).saturating_add(amount));
+        self.update_instrumented();
+    }
+    /// Recomputes [`Interpreter::instrumented`] after a hook is turned on or off
+    fn update_instrumented(&mut self) {
+        self.instrumented = self.fuel.is_some()
+            || self.history.is_some()
+            || self.trace.is_some()
+            || self.profile.is_some()
+            || !self.watchpoints.is_empty();
+    }
+    /// Replaces stdin as the source read by READCHAR and READNUM
+    pub fn set_input(&mut self, source: impl Read + 'static) {
+        self.input = Input::new(source);
+    }
+    /// Replaces buffered stdout as the sink for DEBUG, DEBUGCHAR and DEBUGFLOAT.
+    /// Output is not buffered any further, wrap the sink in a `BufWriter` if needed.
+    pub fn set_output(&mut self, sink: impl Write + 'static) {
+        self.output = Output::new(sink);
+    }
+    /// Writes any buffered output to the sink, and flushes the trace. This happens
+    /// automatically when the program ends or reads input.
+    pub fn flush_output(&mut self) -> Result<(), ErrorKind> {
+        self.output
+            .writer()
+            .flush()
+            .map_err(|_| ErrorKind::OutputError)?;
+        self.flush_trace()
+    }
+    /// Whether DBGSILENT has silenced the program
+    pub fn silent(&self) -> bool {
+        self.silent
+    }
+    pub fn program(&self) -> &[u8] {
+        &self.program
+    }
+    /// Address of the next instruction to execute
+    pub fn program_counter(&self) -> usize {
+        self.program_counter
+    }
+    /// Overwrites the nth item from the top of the stack (0 is the top)
+    /// and leaves the fast path, as the verifier knows nothing about the new value
+    pub fn debug_set_stack(&mut self, nth_from_top: u64, value: u64) -> Result<(), ErrorKind> {
+        self.drop_fast_path();
+        self.set_nth_from_top(nth_from_top, value)
+    }
+    /// Overwrites a memory word and leaves the fast path, like [`Interpreter::debug_set_stack`]
+    pub fn debug_set_memory(&mut self, offset: u64, value: u64) -> Result<(), ErrorKind> {
+        self.drop_fast_path();
+        self.set_memory_offset(offset, value)?;
+        // Only the program's own accesses are watched
+        self.watchpoints.hit = None;
+        Ok(())
     }
     pub fn debug_get_stack(&self) -> &[u64] {
         &self.stack[0..self.stack_length]
@@ -61,81 +215,86 @@ impl Interpreter {
         &self.memory
     }
     #[inline]
-    fn push(&mut self, value: u64) -> Result<(), InterpreterError> {
-        if self.stack_length < STACK_SIZE {
+    fn push(&mut self, value: u64) -> Result<(), ErrorKind> {
+        if self.stack_length < self.stack.len() {
             self.stack[self.stack_length] = value;
             self.stack_length += 1;
             Ok(())
         } else {
-            Err(InterpreterError::StackOverflow)
+            Err(ErrorKind::StackOverflow)
         }
     }
     #[inline]
-    fn pop(&mut self) -> Result<u64, InterpreterError> {
+    fn pop(&mut self) -> Result<u64, ErrorKind> {
         if self.stack_length > 0 {
             let val = self.stack[self.stack_length - 1];
             self.stack_length -= 1;
 
             Ok(val)
         } else {
-            Err(InterpreterError::StackUnderflow)
+            Err(ErrorKind::StackUnderflow)
         }
     }
-    fn goto(&mut self, addr: u64) -> Result<(), InterpreterError> {
-        let addr = addr as usize;
-        if let Some(b) = self.program.get(addr) {
-            if *b != Opcode::GotoTarget.into() {
-                return Err(InterpreterError::InvalidGoto);
-            }
+    /// Checks that `addr` is a GOTOTARGET inside the program
+    fn goto_target(&self, addr: u64) -> Result<usize, ErrorKind> {
+        match self.program.get(addr as usize) {
+            Some(b) if *b == Opcode::GotoTarget.into() => Ok(addr as usize),
+            _ => Err(ErrorKind::InvalidGoto(addr)),
         }
-        if addr < self.program.len() {
-            self.program_counter = addr;
+    }
+    fn goto(&mut self, addr: u64) -> Result<(), ErrorKind> {
+        self.program_counter = self.goto_target(addr)?;
+        Ok(())
+    }
+    fn push_return(&mut self, addr: usize) -> Result<(), ErrorKind> {
+        if self.return_stack_length < self.return_stack.len() {
+            self.return_stack[self.return_stack_length] = addr;
+            self.return_stack_length += 1;
             Ok(())
         } else {
-            Err(InterpreterError::InvalidGoto)
+            Err(ErrorKind::ReturnStackOverflow)
+        }
+    }
+    fn pop_return(&mut self) -> Result<usize, ErrorKind> {
+        if self.return_stack_length > 0 {
+            self.return_stack_length -= 1;
+            Ok(self.return_stack[self.return_stack_length])
+        } else {
+            Err(ErrorKind::ReturnStackUnderflow)
         }
     }
     #[inline]
-    fn pop_two(&mut self) -> Result<(u64, u64), InterpreterError> {
+    fn pop_two(&mut self) -> Result<(u64, u64), ErrorKind> {
         if self.stack_length > 1 {
-            assert!(self.stack_length - 1 < STACK_SIZE);
-            assert!(self.stack_length - 2 < STACK_SIZE);
+            assert!(self.stack_length - 1 < self.stack.len());
+            assert!(self.stack_length - 2 < self.stack.len());
             let a = self.stack.get(self.stack_length - 1).unwrap();
             let b = self.stack.get(self.stack_length - 2).unwrap();
             self.stack_length -= 2;
             Ok((*a, *b))
         } else {
-            Err(InterpreterError::StackUnderflow)
+            Err(ErrorKind::StackUnderflow)
         }
     }
     /// Push with confidence that the stack will not overflow
     #[inline(never)]
     fn confident_push(&mut self, value: u64) {
-        assert!(self.stack_length < STACK_SIZE);
+        assert!(self.stack_length < self.stack.len());
         self.stack[self.stack_length] = value;
         self.stack_length += 1;
     }
-    /// pop with confidence that the stack will not underflow
-    #[inline]
-    fn confident_pop(&mut self) -> u64 {
-        assert!(self.stack_length > 0);
-        let val = self.stack[self.stack_length - 1];
-        // self.stack[self.stack_length - 1] = 0;
-        self.stack_length -= 1;
-        val
-    }
-    fn get_nth_from_top(&self, nth_stack: u64) -> Result<u64, InterpreterError> {
+    fn get_nth_from_top(&self, nth_stack: u64) -> Result<u64, ErrorKind> {
         let nth_stack = nth_stack as usize;
         if nth_stack >= self.stack_length {
-            return Err(InterpreterError::StackUnderflow);
+            return Err(ErrorKind::StackUnderflow);
         }
         let index = self.stack_length - nth_stack - 1;
         Ok(self.stack[index])
     }
-    fn set_nth_from_top(&mut self, nth_stack: u64, value: u64) -> Result<(), InterpreterError> {
+    fn set_nth_from_top(&mut self, nth_stack: u64, value: u64) -> Result<(), ErrorKind> {
         let nth_stack = nth_stack as usize;
         if nth_stack >= self.stack_length {
-            return Err(InterpreterError::StackUnderflow);
+            return Err(ErrorKind::StackUnderflow);
         }
         let index = self.stack_length - nth_stack - 1;
 
@@ -152,35 +311,91 @@ impl Interpreter {
         // self.stack[index] = value;
     }
     #[inline]
-    fn swap_nth(&mut self, nth_stack: u64) -> Result<(), InterpreterError> {
+    fn swap_nth(&mut self, nth_stack: u64) -> Result<(), ErrorKind> {
         let a = self.get_nth_from_top(nth_stack)?;
         let b = self.get_nth_from_top(0)?;
         self.set_nth_from_top_unchecked(nth_stack, b);
         self.set_nth_from_top_unchecked(0, a);
         Ok(())
     }
+    /// Moves the nth item from the top to the top, shifting the items above it down one
+    fn roll_nth(&mut self, nth_stack: u64) -> Result<(), ErrorKind> {
+        self.get_nth_from_top(nth_stack)?;
+        for k in 1..=nth_stack {
+            self.swap_nth(k)?;
+        }
+        Ok(())
+    }
     fn next_byte(&mut self) -> Option<u8> {
         let v = self.program.get(self.program_counter).cloned();
         self.program_counter += 1;
         v
     }
 
-    fn load_memory_offset(&self, offset: u64) -> Result<u64, InterpreterError> {
+    fn load_memory_offset(&mut self, offset: u64) -> Result<u64, ErrorKind> {
+        let value = self.memory_word(offset)?;
+        if self.instrumented && !self.watchpoints.is_empty() {
+            self.watchpoints.check(offset, false, value, value);
+        }
+        Ok(value)
+    }
+    /// Reads a memory word without triggering watchpoints
+    fn memory_word(&self, offset: u64) -> Result<u64, ErrorKind> {
         self.memory
             .get(offset as usize)
             .copied()
-            .ok_or(InterpreterError::InvalidMemoryOffset)
+            .ok_or(ErrorKind::InvalidMemoryOffset(offset))
     }
-    fn set_memory_offset(&mut self, offset: u64, value: u64) -> Result<(), InterpreterError> {
+    fn set_memory_offset(&mut self, offset: u64, value: u64) -> Result<(), ErrorKind> {
         let Some(m) = self.memory.get_mut(offset as usize) else {
-            return Err(InterpreterError::InvalidMemoryOffset);
+            return Err(ErrorKind::InvalidMemoryOffset(offset));
         };
+        if self.instrumented {
+            if let Some(history) = self.history.as_mut() {
+                history.log_memory_write(offset as usize, *m);
+            }
+            if !self.watchpoints.is_empty() {
+                self.watchpoints.check(offset, true, *m, value);
+            }
+            if let Some(trace) = self.trace.as_mut() {
+                trace.log_memory_write(offset as usize, *m, value);
+            }
+        }
         *m = value;
         Ok(())
     }
 
+    /// Splits a byte address into a word offset and a bit shift within that word,
+    /// checking the address is aligned to `width` bytes.
+    fn byte_address(addr: u64, width: u64) -> Result<(u64, u64), ErrorKind> {
+        if !addr.is_multiple_of(width) {
+            return Err(ErrorKind::InvalidMemoryOffset(addr));
+        }
+        Ok((addr / 8, (addr % 8) * 8))
+    }
+    /// Loads `width` (1, 2 or 4) bytes starting at a byte address, zero extended
+    fn load_memory_bytes(&mut self, addr: u64, width: u64) -> Result<u64, ErrorKind> {
+        let (offset, shift) = Self::byte_address(addr, width)?;
+        let mask = (1 << (width * 8)) - 1;
+        let word = self
+            .load_memory_offset(offset)
+            .map_err(|_| ErrorKind::InvalidMemoryOffset(addr))?;
+        Ok((word >> shift) & mask)
+    }
+    /// Stores the lowest `width` (1, 2 or 4) bytes of `value` at a byte address
+    fn store_memory_bytes(&mut self, addr: u64, width: u64, value: u64) -> Result<(), ErrorKind> {
+        let (offset, shift) = Self::byte_address(addr, width)?;
+        let mask: u64 = (1 << (width * 8)) - 1;
+        // Reading the rest of the word is part of the write, not a watched read
+        let word = self
+            .memory_word(offset)
+            .map_err(|_| ErrorKind::InvalidMemoryOffset(addr))?;
+        let word = (word & !(mask << shift)) | ((value & mask) << shift);
+        self.set_memory_offset(offset, word)
+    }
+
     /// parses next instruction parameter and advances the program counter past the instruction parameter.
-    fn read_parameter(&mut self) -> Result<u64, InterpreterError> {
+    fn read_parameter(&mut self) -> Result<u64, ErrorKind> {
         let mut buffer: [u8; 8] = [0; 8];
         (&self.program[self.program_counter..])
             .read_exact(&mut buffer)
@@ -189,12 +404,12 @@ impl Interpreter {
         let v = u64::from_le_bytes(buffer);
         Ok(v)
     }
-    fn read_parameter_byte(&mut self) -> Result<u64, InterpreterError> {
+    fn read_parameter_byte(&mut self) -> Result<u64, ErrorKind> {
         let byte = self.next_byte().unwrap();
         let val = u8::from_le_bytes([byte]);
         Ok(val as u64)
     }
-    fn read_parameter_2byte(&mut self) -> Result<u64, InterpreterError> {
+    fn read_parameter_2byte(&mut self) -> Result<u64, ErrorKind> {
         let mut buffer: [u8; 2] = [0; 2];
         (&self.program[self.program_counter..])
             .read_exact(&mut buffer)
@@ -203,12 +418,942 @@ impl Interpreter {
         let v = u16::from_le_bytes(buffer);
         Ok(v as u64)
     }
+    /// Reads an `N` byte little endian parameter, zero extended to a word.
+    fn read_parameter_nbyte<const N: usize>(&mut self) -> Result<u64, ErrorKind> {
+        let mut buffer: [u8; 8] = [0; 8];
+        (&self.program[self.program_counter..])
+            .read_exact(&mut buffer[..N])
+            .unwrap();
+        self.program_counter += N;
+        let v = u64::from_le_bytes(buffer);
+        Ok(v)
+    }
+
+    /// Jumps to the trap handler with the error code pushed, if a handler is set.
+    /// The handler is cleared so an error inside the handler is not caught by itself.
+    ///
+    /// With a full stack (or a stack of size 0) there is no room for the code, and the
+    /// error is raised as if there were no handler, keeping the stack as is.
+    fn trap(&mut self, error: ErrorKind) -> Result<InterpreterEvent, ErrorKind> {
+        if self.trap_handler.is_none() || self.stack_length == self.stack.len() {
+            return Err(error);
+        }
+        let handler = self.trap_handler.take().unwrap();
+        self.confident_push(error.code());
+        self.program_counter = handler;
+        Ok(InterpreterEvent::Nothing)
+    }
+
+    /// Error raised by the instruction `opcode` at `pc`, with the current stack
+    fn error(&self, kind: ErrorKind, pc: usize, opcode: u8) -> InterpreterError {
+        InterpreterError::new(kind, pc, opcode, self.debug_get_stack())
+    }
 
+    /// Executes the next instruction
+    #[inline]
     pub fn next_instruction(&mut self) -> Result<InterpreterEvent, InterpreterError> {
-        if let Some(instr) = self.next_byte() {
-            self.instruction_table[instr as usize](self)
-        } else {
-            Ok(InterpreterEvent::ProgramEnd)
+        if self.instrumented {
+            return self.next_instruction_instrumented();
+        }
+        let pc = self.program_counter;
+        let Some(&instr) = self.program.get(pc) else {
+            return self.program_end();
+        };
+        let depth = self.stack_length;
+        self.program_counter += 1;
+        match self.instruction_table[instr as usize](self) {
+            Ok(()) => Ok(InterpreterEvent::Nothing),
+            Err(interrupt) => self.interrupted(interrupt, pc, instr, depth),
+        }
+    }
+
+    /// [`Interpreter::next_instruction`] with fuel metering, recording, tracing,
+    /// profiling and watchpoints, used while any of them is on
+    #[inline(never)]
+    fn next_instruction_instrumented(&mut self) -> Result<InterpreterEvent, InterpreterError> {
+        let pc = self.program_counter;
+        let Some(&instr) = self.program.get(pc) else {
+            return self.program_end();
+        };
+        if let Some(fuel) = self.fuel.as_mut() {
+            let cost = self.config.cost_table[instr as usize];
+            if *fuel < cost {
+                return Ok(InterpreterEvent::OutOfFuel);
+            }
+            *fuel -= cost;
+        }
+        if self.history.is_some() {
+            self.record_step(instr);
+        }
+        if self.trace.is_some() {
+            self.trace_start(instr);
+        }
+        let calls = self.return_stack_length;
+        let depth = self.stack_length;
+        self.program_counter += 1;
+        let result = self.instruction_table[instr as usize](self);
+        if self.profile.is_some() {
+            self.profile_step(pc, calls);
+        }
+        if self.trace.is_some() {
+            self.trace_end(&result)
+                .map_err(|kind| self.error(kind, pc, instr))?;
+        }
+        if let Some(hit) = self.watchpoints.hit.take() {
+            if result.is_ok() {
+                return Ok(InterpreterEvent::Watchpoint {
+                    pc,
+                    address: hit.address,
+                    old: hit.old,
+                    new: hit.new,
+                });
+            }
+        }
+        match result {
+            Ok(()) => Ok(InterpreterEvent::Nothing),
+            Err(interrupt) => self.interrupted(interrupt, pc, instr, depth),
+        }
+    }
+
+    /// Event for the instruction `opcode` at `pc`, which ran with `depth` items on the
+    /// stack, interrupting execution
+    fn interrupted(
+        &mut self,
+        interrupt: Interrupt,
+        pc: usize,
+        opcode: u8,
+        depth: usize,
+    ) -> Result<InterpreterEvent, InterpreterError> {
+        match interrupt {
+            Interrupt::ProgramEnd => Ok(InterpreterEvent::ProgramEnd),
+            Interrupt::Silent => Ok(InterpreterEvent::Silent(self.silent)),
+            Interrupt::Breakpoint => Ok(InterpreterEvent::Breakpoint { pc }),
+            Interrupt::Error(kind) => self.fail(kind, pc, opcode, depth),
+        }
+    }
+
+    /// Handles an error raised by the instruction `opcode` at `pc`, trapping it if a
+    /// handler is set. The error shows the stack as it was before the instruction ran
+    /// with `depth` items, as popping operands leaves their slots in place.
+    #[cold]
+    #[inline(never)]
+    fn fail(
+        &mut self,
+        kind: ErrorKind,
+        pc: usize,
+        opcode: u8,
+        depth: usize,
+    ) -> Result<InterpreterEvent, InterpreterError> {
+        self.trap(kind).map_err(|kind| {
+            // The failed instruction may have left the stack half changed
+            self.drop_fast_path();
+            InterpreterError::new(kind, pc, opcode, &self.stack[..depth])
+        })
+    }
+
+    /// Running off the end of the program acts like a HALT there
+    #[cold]
+    #[inline(never)]
+    fn program_end(&mut self) -> Result<InterpreterEvent, InterpreterError> {
+        let pc = self.program_counter;
+        self.flush_output()
+            .map_err(|kind| self.error(kind, pc, Opcode::Halt.into()))?;
+        Ok(InterpreterEvent::ProgramEnd)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        default_cost_table, ErrorKind, Interpreter, InterpreterConfig, InterpreterEvent,
+        SnapshotError, TraceFilter, WatchKind, END_OF_INPUT, SNAPSHOT_VERSION,
+    };
+    use crate::assembler::assemble;
+    use crate::opcode::Opcode;
+    use std::cell::RefCell;
+    use std::io::Write;
+    use std::rc::Rc;
+
+    /// Output sink that can still be read after it is handed to an interpreter
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn run_to_end(interpreter: &mut Interpreter) -> Result<(), ErrorKind> {
+        loop {
+            if let InterpreterEvent::ProgramEnd =
+                interpreter.next_instruction().map_err(|e| e.kind)?
+            {
+                return Ok(());
+            }
+        }
+    }
+
+    fn run(source: &str) -> Result<Interpreter, ErrorKind> {
+        let mut interpreter = Interpreter::new(assemble(source).unwrap());
+        run_to_end(&mut interpreter)?;
+        Ok(interpreter)
+    }
+
+    #[test]
+    fn call_from_two_sites() {
+        let i = run(r#"
+PUSH 3
+CALL :double
+CALL :double
+HALT
+:double
+DUP
+ADD
+RET
+"#)
+        .unwrap();
+        assert_eq!(i.debug_get_stack(), [12]);
+        assert_eq!(i.return_stack_length, 0);
+    }
+
+    #[test]
+    fn return_stack_errors() {
+        assert!(matches!(
+            run("RET\nHALT"),
+            Err(ErrorKind::ReturnStackUnderflow)
+        ));
+        assert!(matches!(
+            run(":forever\nCALL :forever\nHALT"),
+            Err(ErrorKind::ReturnStackOverflow)
+        ));
+
+        // The overflowing CALL is reported where it is, not at the callee
+        let mut i = InterpreterConfig::new()
+            .return_stack_size(1)
+            .build(assemble("CALL :f\nHALT\n:f\nCALL :f\nRET").unwrap());
+        let error = loop {
+            if let Err(e) = i.next_instruction() {
+                break e;
+            }
+        };
+        assert_eq!(error.kind, ErrorKind::ReturnStackOverflow);
+        assert_eq!(error.pc, 21);
+        assert_eq!(i.program_counter(), 22);
+    }
+
+    #[test]
+    fn signed_arithmetic() {
+        let i = run(r#"
+PUSH 2
+PUSH -7
+SDIV
+PUSH 2
+PUSH -7
+SMOD
+PUSH 5
+PUSH -3
+SLT
+PUSH 5
+PUSH -3
+SGT
+PUSH 9
+NEG
+ABS
+PUSH 255
+SEXT8
+PUSH 32768
+SEXT16
+PUSH 2147483647
+SEXT32
+HALT
+"#)
+        .unwrap();
+        let stack: Vec<i64> = i.debug_get_stack().iter().map(|v| *v as i64).collect();
+        assert_eq!(stack, [-3, -1, 1, 0, 9, -1, -32768, 2147483647]);
+    }
+
+    #[test]
+    fn bitwise_and_shifts() {
+        let i = run(r#"
+PUSH 10
+PUSH 12
+AND
+PUSH 10
+PUSH 12
+XOR
+PUSH 4
+PUSH 1
+SHL
+PUSH 64
+PUSH 1
+SHL
+PUSH 100
+PUSH -16
+SAR
+PUSH 65
+PUSH 1
+ROTR
+PUSH -1
+POPCNT
+PUSH 0
+CLZ
+PUSH 8
+CTZ
+HALT
+"#)
+        .unwrap();
+        assert_eq!(
+            i.debug_get_stack(),
+            [8, 6, 16, 0, u64::MAX, 1 << 63, 64, 64, 3]
+        );
+    }
+
+    #[test]
+    fn float_arithmetic() {
+        let i = run(r#"
+PUSH 0.5
+PUSH 3.25
+FADD
+PUSH 2.0
+FMUL
+PUSH 49.0
+FSQRT
+PUSH 7
+ITOF
+FEQ
+PUSH -2.75
+FTOI
+PUSH 0.0
+PUSH 1.0
+FDIV
+HALT
+"#)
+        .unwrap();
+        let stack = i.debug_get_stack();
+        assert_eq!(f64::from_bits(stack[0]), 7.5);
+        assert_eq!(stack[1], 1);
+        assert_eq!(stack[2] as i64, -2);
+        assert_eq!(f64::from_bits(stack[3]), f64::INFINITY);
+    }
+
+    #[test]
+    fn sub_word_memory() {
+        let i = run(r#"
+PUSH 1311768467463790320
+PUSH 0
+MSTORE
+PUSH 65535
+PUSH 2
+MSTORE16
+PUSH 7
+MLOAD8
+PUSH 2
+MLOAD16S
+PUSH 4
+MLOAD32
+PUSH 0
+MLOAD
+HALT
+"#)
+        .unwrap();
+        assert_eq!(
+            i.debug_get_stack(),
+            [0x12, u64::MAX, 0x1234_5678, 0x1234_5678_ffff_def0]
+        );
+        assert!(matches!(
+            run("PUSH 3\nMLOAD16\nHALT"),
+            Err(ErrorKind::InvalidMemoryOffset(3))
+        ));
+        assert!(matches!(
+            run("PUSH 65536\nMLOAD8\nHALT"),
+            Err(ErrorKind::InvalidMemoryOffset(65536))
+        ));
+        assert!(matches!(
+            run("PUSH 1\nPUSH 65540\nMSTORE32\nHALT"),
+            Err(ErrorKind::InvalidMemoryOffset(65540))
+        ));
+    }
+
+    #[test]
+    fn configured_sizes() {
+        let program = vec![
+            Opcode::Push0.into(),
+            Opcode::Push0.into(),
+            Opcode::Halt.into(),
+        ];
+        let mut i = InterpreterConfig::new().stack_size(1).build(program);
+        i.next_instruction().unwrap();
+        assert_eq!(
+            i.next_instruction().unwrap_err().kind,
+            ErrorKind::StackOverflow
+        );
+
+        let i = InterpreterConfig::new().memory_size(1 << 20).build(vec![]);
+        assert_eq!(i.debug_get_memory().len(), 1 << 20);
+    }
+
+    #[test]
+    fn stack_manipulation() {
+        let i = run(r#"
+PUSH 1
+PUSH 2
+PUSH 3
+PUSH 4
+ROLL 3 // [2, 3, 4, 1]
+PUSH 2
+PICK // copy the 3
+ROT
+NIP
+OVER
+TUCK
+DEPTH
+HALT
+"#)
+        .unwrap();
+        assert_eq!(i.debug_get_stack(), [2, 3, 1, 1, 4, 1, 6]);
+        assert!(matches!(
+            run("PUSH 1\nROLL 1\nHALT"),
+            Err(ErrorKind::StackUnderflow)
+        ));
+    }
+
+    #[test]
+    fn host_calls() {
+        let mut i =
+            Interpreter::new(assemble("PUSH 20\nPUSH 22\nHOSTCALL 7\nHOSTCALL 8\nHALT").unwrap());
+        i.register_host_fn(7, |ctx| {
+            let a = ctx.pop()?;
+            let b = ctx.pop()?;
+            ctx.memory_mut()[0] = a + b;
+            ctx.push(1)
+        });
+        assert!(matches!(
+            run_to_end(&mut i),
+            Err(ErrorKind::UnknownHostFunction(8))
+        ));
+        assert_eq!(i.debug_get_memory()[0], 42);
+        assert_eq!(i.debug_get_stack(), [1]);
+
+        // Stepping back over a host call undoes what it did to the machine
+        let mut i = Interpreter::new(assemble("PUSH 20\nPUSH 22\nHOSTCALL 7\nHALT").unwrap());
+        i.register_host_fn(7, |ctx| {
+            ctx.memory_mut()[5] = 1;
+            ctx.stack_mut()[0] = 3;
+            Ok(())
+        });
+        i.start_recording(usize::MAX, 16);
+        run_to_end(&mut i).unwrap();
+        assert_eq!(i.step_back_n(2), 2);
+        assert_eq!(i.program_counter, 6);
+        assert_eq!(i.debug_get_stack(), [20, 22, 7]);
+        assert_eq!(i.debug_get_memory()[5], 0);
+        assert_eq!(i.step_back_n(3), 3);
+        assert_eq!(i.debug_get_stack(), []);
+    }
+
+    #[test]
+    fn reads_input() {
+        let mut i = Interpreter::new(
+            assemble("READCHAR\nREADNUM\nREADNUM\nREADCHAR\nREADNUM\nHALT").unwrap(),
+        );
+        i.set_input("x123\n 45 \r\n".as_bytes());
+        run_to_end(&mut i).unwrap();
+        assert_eq!(
+            i.debug_get_stack(),
+            [b'x' as u64, 123, 45, END_OF_INPUT, END_OF_INPUT]
+        );
+
+        for input in ["abc", "18446744073709551615"] {
+            let mut i = Interpreter::new(assemble("READNUM\nHALT").unwrap());
+            i.set_input(input.as_bytes());
+            assert!(matches!(run_to_end(&mut i), Err(ErrorKind::InvalidInput)));
+        }
+    }
+
+    #[test]
+    fn captures_output() {
+        let buffer = SharedBuffer::default();
+        let mut i = Interpreter::new(assemble(
+            "PUSH 42\nDEBUG\nPUSH 104\nDEBUGCHAR\nPUSH 105\nDEBUGCHAR\nPUSH 1.5\nDEBUGFLOAT\nHALT",
+        ).unwrap());
+        i.set_output(buffer.clone());
+        run_to_end(&mut i).unwrap();
+        assert_eq!(buffer.0.borrow().as_slice(), b"42\nhi1.5\n");
+    }
+
+    #[test]
+    fn trap_handler_catches_errors() {
+        let i = run(r#"
+SETTRAP :handler
+PUSH 0
+PUSH 1
+DIV
+HALT
+:handler
+// [DivideByZero code]
+SETTRAP :second_handler
+POP
+POP // Stack underflow, caught by the second handler
+HALT
+:second_handler
+HALT
+"#)
+        .unwrap();
+        assert_eq!(i.debug_get_stack(), [ErrorKind::StackUnderflow.code()]);
+
+        assert!(matches!(
+            run("SETTRAP :handler\nCLEARTRAP\nPOP\nHALT\n:handler\nHALT"),
+            Err(ErrorKind::StackUnderflow)
+        ));
+        // Errors inside the handler are not caught by it
+        assert!(matches!(
+            run("SETTRAP :handler\nPOP\nHALT\n:handler\nPOP\nPOP\nHALT"),
+            Err(ErrorKind::StackUnderflow)
+        ));
+        // Without room for the error code the error is not caught, and the stack is
+        // left alone
+        let program =
+            assemble("SETTRAP :handler\n:grow\nPUSH 7\nGOTO :grow\nHALT\n:handler\nHALT").unwrap();
+        let mut i = InterpreterConfig::new()
+            .stack_size(2)
+            .build(program.clone());
+        assert!(matches!(run_to_end(&mut i), Err(ErrorKind::StackOverflow)));
+        assert_eq!(i.debug_get_stack(), [7, 7]);
+        let mut i = InterpreterConfig::new().stack_size(0).build(program);
+        assert!(matches!(run_to_end(&mut i), Err(ErrorKind::StackOverflow)));
+        assert_eq!(i.debug_get_stack(), []);
+        let mut i = InterpreterConfig::new().stack_size(3).build(
+            assemble("SETTRAP :handler\nPUSH 1\nPUSH 2\nPUSH 3\nRET\nHALT\n:handler\nHALT")
+                .unwrap(),
+        );
+        assert!(matches!(
+            run_to_end(&mut i),
+            Err(ErrorKind::ReturnStackUnderflow)
+        ));
+        assert_eq!(i.debug_get_stack(), [1, 2, 3]);
+    }
+
+    #[test]
+    fn fuel_metering() {
+        let program = assemble(":loop\nGOTO :loop\nHALT").unwrap();
+        let mut i = InterpreterConfig::new().fuel(100).build(program);
+        let mut executed = 0;
+        while let InterpreterEvent::Nothing = i.next_instruction().unwrap() {
+            executed += 1;
+        }
+        assert_eq!(executed, 100);
+        assert_eq!(i.fuel(), Some(0));
+        let pc = i.program_counter;
+        assert!(matches!(
+            i.next_instruction(),
+            Ok(InterpreterEvent::OutOfFuel)
+        ));
+        assert_eq!(i.program_counter, pc);
+
+        i.add_fuel(1);
+        assert!(matches!(
+            i.next_instruction(),
+            Ok(InterpreterEvent::Nothing)
+        ));
+
+        let mut costs = default_cost_table();
+        costs[Opcode::Div as usize] = 10;
+        let mut i = InterpreterConfig::new()
+            .fuel(11)
+            .cost_table(costs)
+            .build(assemble("PUSH 1\nPUSH 1\nDIV\nHALT").unwrap());
+        while let InterpreterEvent::Nothing = i.next_instruction().unwrap() {}
+        assert_eq!(i.debug_get_stack(), [1, 1]);
+        assert_eq!(i.fuel(), Some(9));
+    }
+
+    #[test]
+    fn snapshot_round_trip() {
+        let program = assemble(
+            "PUSH 5\nPUSH 7\nPUSH 9\nPUSH 1\nMSTORE\nCALL :f\nHALT\n:f\nPUSH 3\nPUSH 3\nADD\nRET",
+        )
+        .unwrap();
+        let mut i = InterpreterConfig::new()
+            .stack_size(16)
+            .memory_size(4)
+            .fuel(1000)
+            .build(program);
+        // Stop inside :f, before the ADD
+        for _ in 0..10 {
+            i.next_instruction().unwrap();
+        }
+        let mut bytes = Vec::new();
+        i.write_snapshot(&mut bytes).unwrap();
+        let mut restored = Interpreter::read_snapshot(&mut bytes.as_slice()).unwrap();
+
+        assert_eq!(restored.config(), i.config());
+        assert_eq!(restored.fuel(), i.fuel());
+        assert_eq!(restored.debug_get_stack(), [5, 7, 3, 3]);
+        assert_eq!(restored.debug_get_memory(), [0, 9, 0, 0]);
+        run_to_end(&mut restored).unwrap();
+        run_to_end(&mut i).unwrap();
+        assert_eq!(restored.debug_get_stack(), i.debug_get_stack());
+
+        bytes[4] = 99;
+        assert!(matches!(
+            Interpreter::read_snapshot(&mut bytes.as_slice()),
+            Err(SnapshotError::UnsupportedVersion(99))
+        ));
+        assert!(matches!(
+            Interpreter::read_snapshot(&mut &bytes[1..]),
+            Err(SnapshotError::NotASnapshot)
+        ));
+        bytes[4] = SNAPSHOT_VERSION as u8;
+
+        // Sizes that are too large, or larger than the snapshot, are not allocated
+        for (field, size) in [
+            (8, u64::MAX),
+            (16, 1u64 << 40),
+            (24, 1 << 40),
+            (24, u64::MAX),
+        ] {
+            let mut damaged = bytes.clone();
+            damaged[field..field + 8].copy_from_slice(&size.to_le_bytes());
+            assert!(matches!(
+                Interpreter::read_snapshot(&mut damaged.as_slice()),
+                Err(SnapshotError::Corrupt)
+            ));
+        }
+    }
+
+    #[test]
+    fn step_back_through_history() {
+        let program = assemble(
+            r#"
+PUSH 0
+:loop
+INC
+DUP
+DUP
+MSTORE
+DUP
+CALL :check
+GOTONZ :loop
+POP
+POP
+HALT
+:check
+PUSH 20
+GT
+RET
+"#,
+        )
+        .unwrap();
+        let mut i = Interpreter::new(program.clone());
+        i.start_recording(usize::MAX, 16);
+        assert_eq!(run_to_end(&mut i).unwrap_err().code(), 3);
+
+        let mut reference = Interpreter::new(program);
+        let mut steps = Vec::new();
+        loop {
+            steps.push((
+                reference.program_counter,
+                reference.debug_get_stack().to_vec(),
+                reference.debug_get_memory()[..24].to_vec(),
+            ));
+            if reference.next_instruction().is_err() {
+                break;
+            }
+        }
+        let recorded = i.recorded_steps();
+        assert_eq!(recorded, steps.len());
+        while let Some((pc, stack, memory)) = steps.pop() {
+            assert!(i.step_back());
+            assert_eq!(i.program_counter, pc);
+            assert_eq!(i.debug_get_stack(), stack);
+            assert_eq!(&i.debug_get_memory()[..24], memory);
+        }
+        assert!(!i.step_back());
+
+        // Jumping back over checkpoints lands on the same state
+        let mut a =
+            Interpreter::new(assemble("PUSH 0\n:l\nINC\nDUP\nDUP\nMSTORE\nGOTO :l\nHALT").unwrap());
+        a.start_recording(usize::MAX, 7);
+        for _ in 0..500 {
+            a.next_instruction().unwrap();
+        }
+        assert_eq!(a.step_back_n(123), 123);
+        let pc = a.program_counter;
+        let stack = a.debug_get_stack().to_vec();
+        let memory = a.debug_get_memory().to_vec();
+        assert!(a.run_back_to(0));
+        assert!(!a.run_back_to(0));
+        for _ in 0..377 {
+            a.next_instruction().unwrap();
+        }
+        assert_eq!(a.program_counter, pc);
+        assert_eq!(a.debug_get_stack(), stack);
+        assert_eq!(a.debug_get_memory(), memory);
+
+        // Old segments are dropped once enough newer history is kept
+        a.start_recording(50, 10);
+        for _ in 0..500 {
+            a.next_instruction().unwrap();
         }
+        let recorded = a.recorded_steps();
+        assert!((50..=60).contains(&recorded));
+        assert_eq!(a.step_back_n(100), recorded);
+    }
+
+    #[test]
+    fn brk_instruction() {
+        let program = assemble("PUSH 1\nBRK\nPUSH 2\nHALT").unwrap();
+        let mut i = Interpreter::new(program.clone());
+        i.next_instruction().unwrap();
+        assert!(matches!(
+            i.next_instruction(),
+            Ok(InterpreterEvent::Breakpoint { pc: 2 })
+        ));
+        assert_eq!(i.debug_get_stack(), [1]);
+        run_to_end(&mut i).unwrap();
+        assert_eq!(i.debug_get_stack(), [1, 2]);
+
+        let mut i = InterpreterConfig::new().brk_enabled(false).build(program);
+        while let InterpreterEvent::Nothing = i.next_instruction().unwrap() {}
+        assert_eq!(i.debug_get_stack(), [1, 2]);
+    }
+
+    #[test]
+    fn fast_path() {
+        let program = assemble(
+            r#"
+SETTRAP :handler
+PUSH 0
+PUSH 5
+:loop
+SWAP
+DUP2
+ADD
+SWAP
+DEC
+DUP
+GOTONZ :loop
+POP
+PUSH 0
+SWAP
+DIV
+HALT
+:handler
+HALT
+"#,
+        )
+        .unwrap();
+        let mut checked = Interpreter::new(program.clone());
+        let mut fast = InterpreterConfig::new().fast_path(true).build(program);
+        assert!(!checked.is_verified());
+        assert!(fast.is_verified());
+        run_to_end(&mut checked).unwrap();
+        run_to_end(&mut fast).unwrap();
+        assert_eq!(fast.debug_get_stack(), [ErrorKind::DivideByZero.code()]);
+        assert_eq!(fast.debug_get_stack(), checked.debug_get_stack());
+
+        // Anything the verifier cannot prove safe runs with the checks
+        let mut i = InterpreterConfig::new().fast_path(true).build(
+            assemble(
+                "PUSH 1
+POP
+POP
+HALT",
+            )
+            .unwrap(),
+        );
+        assert!(!i.is_verified());
+        assert!(matches!(run_to_end(&mut i), Err(ErrorKind::StackUnderflow)));
+    }
+
+    #[test]
+    fn fast_path_only_for_verified_state() {
+        let program = assemble("PUSH 7\nPUSH 1\nPUSH 0\nSWAP\nDIV\nADD\nHALT").unwrap();
+        let config = InterpreterConfig::new().fast_path(true);
+        let snapshot = |i: &Interpreter| {
+            let mut bytes = Vec::new();
+            i.write_snapshot(&mut bytes).unwrap();
+            Interpreter::read_snapshot(&mut bytes.as_slice()).unwrap()
+        };
+
+        // The DIV fails after popping its operands, a state the verifier never saw
+        let mut i = config.clone().build(program.clone());
+        assert!(i.is_verified());
+        assert!(matches!(run_to_end(&mut i), Err(ErrorKind::DivideByZero)));
+        assert!(!i.is_verified());
+        let mut restored = snapshot(&i);
+        assert!(!restored.is_verified());
+        assert!(matches!(
+            run_to_end(&mut restored),
+            Err(ErrorKind::StackUnderflow)
+        ));
+
+        // From before the DIV it is the same state as running from the start
+        let mut i = config.clone().build(program.clone());
+        i.start_recording(1, 1024);
+        assert!(run_to_end(&mut i).is_err());
+        i.step_back();
+        let mut restored = snapshot(&i);
+        assert!(restored.is_verified());
+        assert!(matches!(
+            run_to_end(&mut restored),
+            Err(ErrorKind::DivideByZero)
+        ));
+
+        // Inside a call, with a return address on the return stack
+        let mut i = config
+            .clone()
+            .build(assemble("PUSH 5\nCALL :f\nHALT\n:f\nPUSH 3\nADD\nRET\nHALT").unwrap());
+        assert!(i.is_verified());
+        for _ in 0..4 {
+            i.next_instruction().unwrap();
+        }
+        assert!(snapshot(&i).is_verified());
+
+        let mut i = config.build(program);
+        i.next_instruction().unwrap();
+        i.debug_set_stack(0, 3).unwrap();
+        assert!(!i.is_verified());
+    }
+
+    #[test]
+    fn watchpoints() {
+        let program =
+            assemble("PUSH 7\nPUSH 3\nMSTORE\nPUSH 3\nMLOAD\nPUSH 9\nPUSH 24\nMSTORE8\nHALT")
+                .unwrap();
+        let watch = |offsets, kind| {
+            let mut i = Interpreter::new(program.clone());
+            i.add_watchpoint(offsets, kind);
+            let mut events = Vec::new();
+            loop {
+                match i.next_instruction().unwrap() {
+                    InterpreterEvent::ProgramEnd => break,
+                    InterpreterEvent::Watchpoint {
+                        pc,
+                        address,
+                        old,
+                        new,
+                    } => events.push((pc, address, old, new)),
+                    _ => (),
+                }
+            }
+            events
+        };
+        // The MSTORE8 at byte 24 writes the low byte of word 3
+        assert_eq!(watch(3..4, WatchKind::Write), [(4, 3, 0, 7), (12, 3, 7, 9)]);
+        assert_eq!(watch(2..4, WatchKind::Read), [(7, 3, 7, 7)]);
+        assert_eq!(watch(3..4, WatchKind::ReadWrite).len(), 3);
+        assert_eq!(watch(4..8, WatchKind::ReadWrite), []);
+
+        let mut i = Interpreter::new(program);
+        i.add_watchpoint(3..4, WatchKind::Write);
+        i.debug_set_memory(3, 1).unwrap();
+        assert!(matches!(
+            i.next_instruction(),
+            Ok(InterpreterEvent::Nothing)
+        ));
+        assert!(i.remove_watchpoint(&(3..4)));
+        assert!(i.watchpoints().is_empty());
+    }
+
+    #[test]
+    fn instrumented_only_while_hooked() {
+        let mut i = Interpreter::new(assemble("HALT").unwrap());
+        assert!(!i.instrumented);
+        i.start_recording(1, 1);
+        i.start_profiling();
+        i.add_watchpoint(0..1, WatchKind::Read);
+        i.stop_recording();
+        i.stop_profiling();
+        assert!(i.instrumented);
+        i.clear_watchpoints();
+        assert!(!i.instrumented);
+        i.set_fuel(Some(1));
+        assert!(i.instrumented);
+        i.set_fuel(None);
+        assert!(!i.instrumented);
+    }
+
+    #[test]
+    fn trace_output() {
+        let program = assemble(
+            "PUSH 7\nPUSH 3\nMSTORE\nDBGSILENT\nPUSH 1\nDBGSILENT\nPUSH 0\nPUSH 0\nDIV\nHALT",
+        )
+        .unwrap();
+        let trace = |filter| {
+            let buffer = SharedBuffer::default();
+            let mut i = Interpreter::new(program.clone());
+            i.start_trace(buffer.clone(), filter);
+            let result = run_to_end(&mut i);
+            assert!(matches!(result, Err(ErrorKind::DivideByZero)));
+            i.stop_trace().unwrap();
+            let lines = String::from_utf8(buffer.0.take()).unwrap();
+            lines.lines().map(str::to_string).collect::<Vec<_>>()
+        };
+
+        let lines = trace(TraceFilter::default());
+        assert_eq!(lines.len(), 9);
+        assert_eq!(
+            lines[0],
+            r#"{"pc":0,"op":"PUSH1","imm":7,"depth":1,"top":[7],"writes":[]}"#
+        );
+        assert_eq!(
+            lines[2],
+            r#"{"pc":4,"op":"MSTORE","imm":null,"depth":0,"top":[],"writes":[{"offset":3,"old":0,"new":7}]}"#
+        );
+        assert!(lines[8].ends_with(r#""error":"DivideByZero"}"#));
+
+        let lines = trace(TraceFilter {
+            pc_ranges: vec![0..2, 8..9],
+            skip_silent: false,
+        });
+        assert_eq!(lines.len(), 2);
+        assert!(lines[1].starts_with(r#"{"pc":8,"op":"DBGSILENT""#));
+
+        // The second DBGSILENT runs while silent, so it is left out too
+        let lines = trace(TraceFilter {
+            pc_ranges: Vec::new(),
+            skip_silent: true,
+        });
+        assert_eq!(lines.len(), 7);
+    }
+
+    #[test]
+    fn errors_carry_context() {
+        let mut i = Interpreter::new(assemble("PUSH 1\nPUSH 2\nPUSH 5\nGOTO\nHALT").unwrap());
+        let e = loop {
+            if let Err(e) = i.next_instruction() {
+                break e;
+            }
+        };
+        assert_eq!(e.kind, ErrorKind::InvalidGoto(5));
+        assert_eq!((e.pc, e.opcode), (6, Opcode::Goto.into()));
+        assert_eq!(e.operand(), Some(5));
+        assert_eq!(e.stack, [1, 2, 5]);
+        assert_eq!(e.code(), 1);
+        assert_eq!(
+            e.to_string(),
+            "GOTO at 6: address 5 is not a GOTOTARGET, stack [1, 2, 5]"
+        );
+
+        // The stack is from before the operands were popped, showing the zero divisor
+        let mut i = Interpreter::new(assemble("PUSH 4\nPUSH 0\nPUSH 7\nDIV\nHALT").unwrap());
+        let e = loop {
+            if let Err(e) = i.next_instruction() {
+                break e;
+            }
+        };
+        assert_eq!(e.kind, ErrorKind::DivideByZero);
+        assert_eq!(e.stack, [4, 0, 7]);
+
+        // Caught errors still push their code
+        let i = run("SETTRAP :handler\nPUSH 0\nMLOAD8S\nPUSH 1\nMLOAD16\n:handler\nHALT").unwrap();
+        assert_eq!(
+            i.debug_get_stack(),
+            [0, ErrorKind::InvalidMemoryOffset(1).code()]
+        );
     }
 }
@@ -0,0 +1,103 @@
+//! Execution counts for profiling.
+//!
+//! Every executed instruction is counted by its address, and by the chain of CALL
+//! instructions that led to it so the counts can be shown as call stacks. Steps
+//! undone with [`Interpreter::step_back`] stay counted.
+
+use std::collections::HashMap;
+
+use super::Interpreter;
+
+/// Counts collected while profiling
+#[derive(Debug, Clone, Default)]
+pub struct Profile {
+    /// Executions per address
+    counts: Vec<u64>,
+    /// (parent, address of the CALL) of each call context, 0 is the top level
+    contexts: Vec<(usize, usize)>,
+    context_ids: HashMap<(usize, usize), usize>,
+    /// Executions per (context, address)
+    context_counts: HashMap<(usize, usize), u64>,
+    current: usize,
+}
+
+impl Profile {
+    fn new(program_length: usize) -> Profile {
+        Profile {
+            counts: vec![0; program_length],
+            contexts: vec![(0, 0)],
+            ..Profile::default()
+        }
+    }
+
+    /// Counts the instruction at `pc`, given the return stack length before and after it
+    fn record(&mut self, pc: usize, calls_before: usize, calls_after: usize) {
+        self.counts[pc] += 1;
+        *self.context_counts.entry((self.current, pc)).or_insert(0) += 1;
+        if calls_after > calls_before {
+            let next_id = self.contexts.len();
+            let id = *self
+                .context_ids
+                .entry((self.current, pc))
+                .or_insert(next_id);
+            if id == next_id {
+                self.contexts.push((self.current, pc));
+            }
+            self.current = id;
+        } else if calls_after < calls_before {
+            // Returning from the top level, e.g. after resuming a snapshot, stays there
+            self.current = self.contexts[self.current].0;
+        }
+    }
+
+    /// Executions per address
+    pub fn counts(&self) -> &[u64] {
+        &self.counts
+    }
+    /// Total instructions executed
+    pub fn total(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+    /// Executions per call stack, as (addresses of the CALLs outermost first,
+    /// address of the instruction, count), in no particular order
+    pub fn stacks(&self) -> Vec<(Vec<usize>, usize, u64)> {
+        self.context_counts
+            .iter()
+            .map(|(&(context, pc), &count)| {
+                let mut calls = Vec::new();
+                let mut context = context;
+                while context != 0 {
+                    let (parent, call) = self.contexts[context];
+                    calls.push(call);
+                    context = parent;
+                }
+                calls.reverse();
+                (calls, pc, count)
+            })
+            .collect()
+    }
+}
+
+impl Interpreter {
+    /// Starts counting executed instructions, discarding any previous profile
+    pub fn start_profiling(&mut self) {
+        self.profile = Some(Profile::new(self.program.len()));
+        self.update_instrumented();
+    }
+    /// Stops profiling, returning the counts collected
+    pub fn stop_profiling(&mut self) -> Option<Profile> {
+        let profile = self.profile.take();
+        self.update_instrumented();
+        profile
+    }
+    pub fn profile(&self) -> Option<&Profile> {
+        self.profile.as_ref()
+    }
+
+    #[inline]
+    pub(super) fn profile_step(&mut self, pc: usize, calls_before: usize) {
+        if let Some(profile) = self.profile.as_mut() {
+            profile.record(pc, calls_before, self.return_stack_length);
+        }
+    }
+}
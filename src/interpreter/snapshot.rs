@@ -0,0 +1,218 @@
+//! Versioned binary snapshots of the full machine state.
+//!
+//! All integers are little endian. Layout of version 4:
+//!
+//! ```text
+//! magic "XSNP", version u32
+//! config:  stack_size u64, return_stack_size u64, memory_size u64,
+//!          fuel option, cost_table 256 x u64, brk_enabled u8,
+//!          fast_path u8
+//! state:   program_counter u64, trap_handler option, fuel option, silent u8,
+//!          program (length u64, bytes), stack (length u64, words),
+//!          return stack (length u64, words), memory (memory_size words)
+//! ```
+//! An option is a u8 flag followed by a u64 that is only present when the flag is 1.
+//!
+//! Host functions, input and output are not part of the snapshot and are reset to
+//! their defaults on load.
+
+use std::io::{Read, Write};
+
+use super::{Interpreter, InterpreterConfig};
+
+const MAGIC: [u8; 4] = *b"XSNP";
+/// Largest stack or return stack size accepted, so a damaged snapshot does not
+/// allocate whatever it asks for
+const MAX_STACK_SIZE: u64 = 1 << 24;
+pub const SNAPSHOT_VERSION: u32 = 4;
+
+#[derive(Debug)]
+pub enum SnapshotError {
+    Io(std::io::Error),
+    /// Input does not start with the snapshot magic bytes
+    NotASnapshot,
+    UnsupportedVersion(u32),
+    /// Snapshot contents are inconsistent, e.g. a stack longer than the stack size
+    Corrupt,
+}
+
+impl From<std::io::Error> for SnapshotError {
+    fn from(value: std::io::Error) -> Self {
+        SnapshotError::Io(value)
+    }
+}
+
+fn write_u64(w: &mut impl Write, v: u64) -> std::io::Result<()> {
+    w.write_all(&v.to_le_bytes())
+}
+
+fn write_option(w: &mut impl Write, v: Option<u64>) -> std::io::Result<()> {
+    match v {
+        Some(v) => {
+            w.write_all(&[1])?;
+            write_u64(w, v)
+        }
+        None => w.write_all(&[0]),
+    }
+}
+
+fn read_u64(r: &mut impl Read) -> std::io::Result<u64> {
+    let mut buffer = [0; 8];
+    r.read_exact(&mut buffer)?;
+    Ok(u64::from_le_bytes(buffer))
+}
+
+fn read_option(r: &mut impl Read) -> Result<Option<u64>, SnapshotError> {
+    let mut flag = [0];
+    r.read_exact(&mut flag)?;
+    match flag[0] {
+        0 => Ok(None),
+        1 => Ok(Some(read_u64(r)?)),
+        _ => Err(SnapshotError::Corrupt),
+    }
+}
+
+fn read_bool(r: &mut impl Read) -> Result<bool, SnapshotError> {
+    let mut flag = [0];
+    r.read_exact(&mut flag)?;
+    match flag[0] {
+        0 => Ok(false),
+        1 => Ok(true),
+        _ => Err(SnapshotError::Corrupt),
+    }
+}
+
+/// Reads a stack size, rejecting sizes over `max`
+fn read_size(r: &mut impl Read, max: u64) -> Result<usize, SnapshotError> {
+    let size = read_u64(r)?;
+    if size > max {
+        return Err(SnapshotError::Corrupt);
+    }
+    Ok(size as usize)
+}
+
+/// Reads exactly `len` bytes, allocating only as much as the input holds
+fn read_bytes(r: &mut impl Read, len: u64) -> Result<Vec<u8>, SnapshotError> {
+    let mut bytes = Vec::new();
+    r.by_ref().take(len).read_to_end(&mut bytes)?;
+    if bytes.len() as u64 != len {
+        return Err(SnapshotError::Corrupt);
+    }
+    Ok(bytes)
+}
+
+/// Reads a length prefix, rejecting lengths over `max`
+fn read_len(r: &mut impl Read, max: usize) -> Result<usize, SnapshotError> {
+    let len = read_u64(r)?;
+    if len > max as u64 {
+        return Err(SnapshotError::Corrupt);
+    }
+    Ok(len as usize)
+}
+
+impl Interpreter {
+    /// Writes the machine state and config as a snapshot. This makes many small writes,
+    /// so `w` should be buffered.
+    pub fn write_snapshot(&self, w: &mut impl Write) -> std::io::Result<()> {
+        w.write_all(&MAGIC)?;
+        w.write_all(&SNAPSHOT_VERSION.to_le_bytes())?;
+
+        let config = &self.config;
+        write_u64(w, config.stack_size as u64)?;
+        write_u64(w, config.return_stack_size as u64)?;
+        write_u64(w, config.memory_size as u64)?;
+        write_option(w, config.fuel)?;
+        for cost in config.cost_table {
+            write_u64(w, cost)?;
+        }
+        w.write_all(&[config.brk_enabled as u8])?;
+        w.write_all(&[config.fast_path as u8])?;
+
+        write_u64(w, self.program_counter as u64)?;
+        write_option(w, self.trap_handler.map(|addr| addr as u64))?;
+        write_option(w, self.fuel)?;
+        w.write_all(&[self.silent as u8])?;
+        write_u64(w, self.program.len() as u64)?;
+        w.write_all(&self.program)?;
+        write_u64(w, self.stack_length as u64)?;
+        for v in self.debug_get_stack() {
+            write_u64(w, *v)?;
+        }
+        write_u64(w, self.return_stack_length as u64)?;
+        for addr in &self.return_stack[..self.return_stack_length] {
+            write_u64(w, *addr as u64)?;
+        }
+        for v in self.memory.iter() {
+            write_u64(w, *v)?;
+        }
+        Ok(())
+    }
+
+    /// Restores an interpreter from a snapshot written by [`Interpreter::write_snapshot`].
+    /// The instruction table is rebuilt, and host functions and I/O are the defaults.
+    /// The fast path is only kept for a state the verifier accounts for.
+    /// `r` should be buffered.
+    pub fn read_snapshot(r: &mut impl Read) -> Result<Interpreter, SnapshotError> {
+        let mut magic = [0; 4];
+        r.read_exact(&mut magic)?;
+        if magic != MAGIC {
+            return Err(SnapshotError::NotASnapshot);
+        }
+        let mut version = [0; 4];
+        r.read_exact(&mut version)?;
+        let version = u32::from_le_bytes(version);
+        if version != SNAPSHOT_VERSION {
+            return Err(SnapshotError::UnsupportedVersion(version));
+        }
+
+        let stack_size = read_size(r, MAX_STACK_SIZE)?;
+        let return_stack_size = read_size(r, MAX_STACK_SIZE)?;
+        let memory_size = read_u64(r)?;
+        let mut config = InterpreterConfig::new()
+            .stack_size(stack_size)
+            .return_stack_size(return_stack_size);
+        config.fuel = read_option(r)?;
+        for cost in config.cost_table.iter_mut() {
+            *cost = read_u64(r)?;
+        }
+        config.brk_enabled = read_bool(r)?;
+        config.fast_path = read_bool(r)?;
+
+        let program_counter = read_u64(r)? as usize;
+        let trap_handler = read_option(r)?.map(|addr| addr as usize);
+        let fuel = read_option(r)?;
+        let silent = read_bool(r)?;
+        let program_len = read_u64(r)?;
+        let program = read_bytes(r, program_len)?;
+        let stack_length = read_len(r, stack_size)?;
+        let stack = (0..stack_length)
+            .map(|_| read_u64(r))
+            .collect::<Result<Vec<_>, _>>()?;
+        let return_stack_length = read_len(r, return_stack_size)?;
+        let return_stack = (0..return_stack_length)
+            .map(|_| read_u64(r).map(|addr| addr as usize))
+            .collect::<Result<Vec<_>, _>>()?;
+        // Read before anything is allocated for it, so a damaged size cannot ask for
+        // more memory than the snapshot holds
+        let memory = read_bytes(r, memory_size.checked_mul(8).ok_or(SnapshotError::Corrupt)?)?;
+        let memory: Vec<u64> = memory
+            .chunks_exact(8)
+            .map(|word| u64::from_le_bytes(word.try_into().unwrap()))
+            .collect();
+
+        let mut interpreter = config.memory_size(memory.len()).build(program);
+        interpreter.program_counter = program_counter;
+        interpreter.trap_handler = trap_handler;
+        interpreter.set_fuel(fuel);
+        interpreter.silent = silent;
+        interpreter.stack_length = stack_length;
+        interpreter.stack[..stack_length].copy_from_slice(&stack);
+        interpreter.return_stack_length = return_stack_length;
+        interpreter.return_stack[..return_stack_length].copy_from_slice(&return_stack);
+        interpreter.memory = memory.into_boxed_slice();
+        if !interpreter.state_is_verified() {
+            interpreter.drop_fast_path();
+        }
+        Ok(interpreter)
+    }
+}
@@ -0,0 +1,167 @@
+//! Execution traces written as JSON lines.
+//!
+//! Each traced instruction produces one line such as
+//! ```text
+//! {"pc":12,"op":"PUSH1","imm":3,"depth":2,"top":[7,3],"writes":[]}
+//! {"pc":14,"op":"MSTORE","imm":null,"depth":0,"top":[],"writes":[{"offset":3,"old":0,"new":7}]}
+//! ```
+//! `depth` and `top` describe the stack after the instruction ran, with the top of
+//! the stack last. An instruction that failed has an extra `"error"` field.
+
+use std::io::Write;
+use std::ops::Range;
+
+use super::instruction::Interrupt;
+use super::{ErrorKind, Interpreter};
+use crate::opcode::Opcode;
+use crate::parser::decode_instruction;
+
+/// Stack items included in each trace line
+const TOP_VALUES: usize = 4;
+
+/// Which executed instructions are traced
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TraceFilter {
+    /// Only trace instructions at these addresses, all addresses when empty
+    pub pc_ranges: Vec<Range<usize>>,
+    /// Skip instructions executed while DBGSILENT has silenced the program
+    pub skip_silent: bool,
+}
+
+impl TraceFilter {
+    fn includes(&self, pc: usize, silent: bool) -> bool {
+        (self.pc_ranges.is_empty() || self.pc_ranges.iter().any(|r| r.contains(&pc)))
+            && !(self.skip_silent && silent)
+    }
+}
+
+/// Instruction being traced
+#[derive(Debug)]
+struct Line {
+    pc: usize,
+    opcode: u8,
+    immediate: Option<u64>,
+    /// (offset, old, new)
+    writes: Vec<(usize, u64, u64)>,
+}
+
+pub(super) struct Trace {
+    sink: Box<dyn Write>,
+    filter: TraceFilter,
+    line: Option<Line>,
+}
+
+impl std::fmt::Debug for Trace {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Trace")
+            .field("filter", &self.filter)
+            .field("line", &self.line)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Trace {
+    /// Logs a memory write by the instruction being traced
+    pub(super) fn log_memory_write(&mut self, offset: usize, old: u64, new: u64) {
+        if let Some(line) = self.line.as_mut() {
+            line.writes.push((offset, old, new));
+        }
+    }
+}
+
+impl Interpreter {
+    /// Writes a line to `sink` for every executed instruction passing `filter`,
+    /// replacing any previous trace. The sink should be buffered.
+    pub fn start_trace(&mut self, sink: impl Write + 'static, filter: TraceFilter) {
+        self.trace = Some(Trace {
+            sink: Box::new(sink),
+            filter,
+            line: None,
+        });
+        self.update_instrumented();
+    }
+    /// Stops tracing, flushing the sink
+    pub fn stop_trace(&mut self) -> Result<(), ErrorKind> {
+        self.flush_trace()?;
+        self.trace = None;
+        self.update_instrumented();
+        Ok(())
+    }
+    pub(super) fn flush_trace(&mut self) -> Result<(), ErrorKind> {
+        match self.trace.as_mut() {
+            Some(trace) => trace.sink.flush().map_err(|_| ErrorKind::OutputError),
+            None => Ok(()),
+        }
+    }
+
+    /// Starts the trace line for `instr`, called before it executes with the
+    /// program counter still on it.
+    pub(super) fn trace_start(&mut self, instr: u8) {
+        let pc = self.program_counter;
+        let Some(trace) = self.trace.as_mut() else {
+            return;
+        };
+        if !trace.filter.includes(pc, self.silent) {
+            return;
+        }
+        let immediate = decode_instruction(&self.program, pc)
+            .and_then(Result::ok)
+            .and_then(|decoded| decoded.immediate);
+        trace.line = Some(Line {
+            pc,
+            opcode: instr,
+            immediate,
+            writes: Vec::new(),
+        });
+    }
+
+    /// Writes the trace line for the instruction that just executed, if it is traced
+    pub(super) fn trace_end(&mut self, result: &Result<(), Interrupt>) -> Result<(), ErrorKind> {
+        let stack = &self.stack[..self.stack_length];
+        let Some(trace) = self.trace.as_mut() else {
+            return Ok(());
+        };
+        let Some(line) = trace.line.take() else {
+            return Ok(());
+        };
+        let error = match result {
+            Err(Interrupt::Error(kind)) => Some(kind),
+            _ => None,
+        };
+        write_line(&mut trace.sink, &line, stack, error).map_err(|_| ErrorKind::OutputError)
+    }
+}
+
+fn write_line(
+    w: &mut impl Write,
+    line: &Line,
+    stack: &[u64],
+    error: Option<&ErrorKind>,
+) -> std::io::Result<()> {
+    let op = Opcode::from_byte(line.opcode)
+        .map_or_else(|| format!("DB {}", line.opcode), |o| o.to_string());
+    write!(w, "{{\"pc\":{},\"op\":\"{op}\",\"imm\":", line.pc)?;
+    match line.immediate {
+        Some(v) => write!(w, "{v}")?,
+        None => write!(w, "null")?,
+    }
+    write!(w, ",\"depth\":{},\"top\":[", stack.len())?;
+    let top = &stack[stack.len().saturating_sub(TOP_VALUES)..];
+    for (i, v) in top.iter().enumerate() {
+        let separator = if i == 0 { "" } else { "," };
+        write!(w, "{separator}{v}")?;
+    }
+    write!(w, "],\"writes\":[")?;
+    for (i, (offset, old, new)) in line.writes.iter().enumerate() {
+        let separator = if i == 0 { "" } else { "," };
+        write!(
+            w,
+            "{separator}{{\"offset\":{offset},\"old\":{old},\"new\":{new}}}"
+        )?;
+    }
+    write!(w, "]")?;
+    if let Some(error) = error {
+        write!(w, ",\"error\":\"{error:?}\"")?;
+    }
+    writeln!(w, "}}")
+}
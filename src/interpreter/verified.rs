@@ -0,0 +1,290 @@
+//! Instructions for programs the verifier has proven safe.
+//!
+//! [`verify`](crate::parser::verify) proves that no path through such a program
+//! underflows or overflows the stack, and that every jump address is a GOTOTARGET
+//! pushed by the instruction right before the jump. The instructions here rely on
+//! that instead of checking the stack depth and jump target each time. Everything
+//! else, division by zero, memory offsets and the return stack, is still checked,
+//! and instructions the verifier says nothing about keep their checked versions.
+//!
+//! The proof only covers states reached by running the program from the start. An
+//! interpreter given any other state, by a snapshot, the debugger or a failed
+//! instruction, goes back to the checked instructions unless the state is one the
+//! verifier has accounted for.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use super::instruction::{get_instruction_table, InstructionTable, Interrupt};
+use super::{ErrorKind, Interpreter};
+use crate::opcode::Opcode;
+use crate::parser::verifier::decode;
+use crate::parser::verify;
+
+impl Interpreter {
+    #[inline(always)]
+    fn verified_pop(&mut self) -> u64 {
+        self.stack_length -= 1;
+        self.stack[self.stack_length]
+    }
+    #[inline(always)]
+    fn verified_push(&mut self, value: u64) {
+        self.stack[self.stack_length] = value;
+        self.stack_length += 1;
+    }
+    /// Item `n` below the top of the stack (0 is the top)
+    #[inline(always)]
+    fn verified_nth(&self, n: usize) -> u64 {
+        self.stack[self.stack_length - 1 - n]
+    }
+    #[inline(always)]
+    fn verified_swap(&mut self, n: usize) {
+        let top = self.stack_length - 1;
+        self.stack.swap(top, top - n);
+    }
+    /// Pops two items and pushes `f(top, second)`, the operand order of `pop_two`
+    #[inline(always)]
+    fn verified_binary(&mut self, f: impl FnOnce(u64, u64) -> u64) {
+        let a = self.verified_pop();
+        let second = self.stack_length - 1;
+        self.stack[second] = f(a, self.stack[second]);
+    }
+}
+
+impl Interpreter {
+    /// Switches to the checked instructions, for state the verifier has not seen
+    pub(super) fn drop_fast_path(&mut self) {
+        if self.verified {
+            self.verified = false;
+            self.instruction_table = get_instruction_table();
+        }
+    }
+
+    /// Whether running on from the current state stays within what the verifier proved:
+    /// the stack depth is one it found at the program counter, return addresses follow
+    /// a CALL, the trap handler can be entered with any depth, and a jump about to run
+    /// uses the address pushed right before it.
+    pub(super) fn state_is_verified(&self) -> bool {
+        let verification = verify(&self.program, self.config.stack_size);
+        if !verification.is_safe() {
+            return false;
+        }
+        let depth = |addr: usize| verification.depths.get(&addr).copied();
+        // Opcode and PUSH constant of each instruction, by address
+        let instructions: BTreeMap<_, _> = decode(&self.program, &mut BTreeSet::new())
+            .into_iter()
+            .map(|i| (i.addr, (i.opcode, i.constant)))
+            .collect();
+        let previous = |addr: usize| instructions.range(..addr).next_back().map(|(_, i)| *i);
+
+        let pc = self.program_counter;
+        if !depth(pc).is_some_and(|(min, max)| (min..=max).contains(&self.stack_length)) {
+            return false;
+        }
+        let jumps = [Opcode::Goto, Opcode::GotoNz, Opcode::Call, Opcode::SetTrap];
+        if let Some((Some(opcode), _)) = instructions.get(&pc) {
+            if jumps.contains(opcode)
+                && previous(pc).and_then(|(_, c)| c) != Some(self.verified_nth(0))
+            {
+                return false;
+            }
+        }
+        let returns_ok = self.return_stack[..self.return_stack_length]
+            .iter()
+            .all(|&addr| {
+                depth(addr).is_some()
+                    && previous(addr).is_some_and(|(o, _)| o == Some(Opcode::Call))
+            });
+        let handler_ok = self.trap_handler.is_none_or(|addr| {
+            let entry = (1, self.config.stack_size.max(1));
+            instructions.get(&addr).map(|(o, _)| *o) == Some(Some(Opcode::GotoTarget))
+                && depth(addr).is_some_and(|(min, max)| min <= entry.0 && max >= entry.1)
+        });
+        returns_ok && handler_ok
+    }
+}
+
+/// The checked instruction table with the stack and jump checks taken out where the
+/// verifier has proven them unnecessary
+pub fn get_verified_instruction_table() -> InstructionTable {
+    let mut table = get_instruction_table();
+    table[1] = pop;
+    table[2] = add;
+    table[3] = sub;
+    table[4] = mul;
+    table[5] = div;
+    table[6] = goto;
+    table[8] = dup;
+    table[9] = swap;
+    table[10] = rem;
+    table[11] = mem_load;
+    table[12] = mem_store;
+    table[13] = goto_nz;
+    table[14] = eq;
+    table[15] = lt;
+    table[16] = gt;
+    table[17] = swap2;
+    table[18] = dup_n::<1>;
+    table[19] = not;
+    table[20] = dup_n::<2>;
+    table[21] = dup_n::<3>;
+    table[28] = nip;
+    table[30] = drop2;
+    table[32] = push0;
+    table[33] = push1;
+    table[34] = push2;
+    table[35] = push_n::<3>;
+    table[36] = push_n::<4>;
+    table[37] = push_n::<5>;
+    table[38] = push_n::<6>;
+    table[39] = push_n::<7>;
+    table[40] = push8;
+    table
+}
+
+fn pop(i: &mut Interpreter) -> Result<(), Interrupt> {
+    i.verified_pop();
+    Ok(())
+}
+
+fn add(i: &mut Interpreter) -> Result<(), Interrupt> {
+    i.verified_binary(u64::wrapping_add);
+    Ok(())
+}
+
+fn sub(i: &mut Interpreter) -> Result<(), Interrupt> {
+    i.verified_binary(u64::wrapping_sub);
+    Ok(())
+}
+
+fn mul(i: &mut Interpreter) -> Result<(), Interrupt> {
+    i.verified_binary(u64::wrapping_mul);
+    Ok(())
+}
+
+fn div(i: &mut Interpreter) -> Result<(), Interrupt> {
+    if i.verified_nth(1) == 0 {
+        i.stack_length -= 2;
+        return Err(ErrorKind::DivideByZero.into());
+    }
+    i.verified_binary(u64::wrapping_div);
+    Ok(())
+}
+
+fn rem(i: &mut Interpreter) -> Result<(), Interrupt> {
+    if i.verified_nth(1) == 0 {
+        i.stack_length -= 2;
+        return Err(ErrorKind::DivideByZero.into());
+    }
+    i.verified_binary(|a, b| a % b);
+    Ok(())
+}
+
+fn eq(i: &mut Interpreter) -> Result<(), Interrupt> {
+    i.verified_binary(|a, b| (a == b) as u64);
+    Ok(())
+}
+
+fn lt(i: &mut Interpreter) -> Result<(), Interrupt> {
+    i.verified_binary(|a, b| (a < b) as u64);
+    Ok(())
+}
+
+fn gt(i: &mut Interpreter) -> Result<(), Interrupt> {
+    i.verified_binary(|a, b| (a > b) as u64);
+    Ok(())
+}
+
+fn not(i: &mut Interpreter) -> Result<(), Interrupt> {
+    let top = i.stack_length - 1;
+    i.stack[top] = !i.stack[top];
+    Ok(())
+}
+
+fn goto(i: &mut Interpreter) -> Result<(), Interrupt> {
+    i.program_counter = i.verified_pop() as usize;
+    Ok(())
+}
+
+fn goto_nz(i: &mut Interpreter) -> Result<(), Interrupt> {
+    let addr = i.verified_pop();
+    if i.verified_pop() != 0 {
+        i.program_counter = addr as usize;
+    }
+    Ok(())
+}
+
+fn mem_load(i: &mut Interpreter) -> Result<(), Interrupt> {
+    let index = i.verified_pop();
+    let val = i.load_memory_offset(index)?;
+    i.verified_push(val);
+    Ok(())
+}
+
+fn mem_store(i: &mut Interpreter) -> Result<(), Interrupt> {
+    let index = i.verified_pop();
+    let val = i.verified_pop();
+    i.set_memory_offset(index, val)?;
+    Ok(())
+}
+
+fn dup(i: &mut Interpreter) -> Result<(), Interrupt> {
+    i.verified_push(i.verified_nth(0));
+    Ok(())
+}
+
+/// DUP2 to DUP4, copying item `N` below the top
+fn dup_n<const N: usize>(i: &mut Interpreter) -> Result<(), Interrupt> {
+    i.verified_push(i.verified_nth(N));
+    Ok(())
+}
+
+fn swap(i: &mut Interpreter) -> Result<(), Interrupt> {
+    i.verified_swap(1);
+    Ok(())
+}
+
+fn swap2(i: &mut Interpreter) -> Result<(), Interrupt> {
+    i.verified_swap(2);
+    Ok(())
+}
+
+fn nip(i: &mut Interpreter) -> Result<(), Interrupt> {
+    let top = i.verified_pop();
+    let new_top = i.stack_length - 1;
+    i.stack[new_top] = top;
+    Ok(())
+}
+
+fn drop2(i: &mut Interpreter) -> Result<(), Interrupt> {
+    i.stack_length -= 2;
+    Ok(())
+}
+
+fn push0(i: &mut Interpreter) -> Result<(), Interrupt> {
+    i.verified_push(0);
+    Ok(())
+}
+
+fn push1(i: &mut Interpreter) -> Result<(), Interrupt> {
+    let v = i.read_parameter_byte()?;
+    i.verified_push(v);
+    Ok(())
+}
+
+fn push2(i: &mut Interpreter) -> Result<(), Interrupt> {
+    let v = i.read_parameter_2byte()?;
+    i.verified_push(v);
+    Ok(())
+}
+
+fn push_n<const N: usize>(i: &mut Interpreter) -> Result<(), Interrupt> {
+    let v = i.read_parameter_nbyte::<N>()?;
+    i.verified_push(v);
+    Ok(())
+}
+
+fn push8(i: &mut Interpreter) -> Result<(), Interrupt> {
+    let v = i.read_parameter()?;
+    i.verified_push(v);
+    Ok(())
+}
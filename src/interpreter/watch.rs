@@ -0,0 +1,93 @@
+//! Memory watchpoints.
+//!
+//! Accesses to watched memory words by the running program are noted while the
+//! instruction executes, and once it has finished [`Interpreter::next_instruction`]
+//! returns [`InterpreterEvent::Watchpoint`](super::InterpreterEvent::Watchpoint)
+//! instead of [`InterpreterEvent::Nothing`](super::InterpreterEvent::Nothing).
+//! Only the first access in an instruction is reported. Memory changed by host
+//! functions or the `debug_set_memory` method is not watched.
+
+use std::ops::Range;
+
+use super::Interpreter;
+
+/// Kind of memory access a watchpoint stops on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchKind {
+    Read,
+    Write,
+    ReadWrite,
+}
+
+impl WatchKind {
+    fn matches(self, write: bool) -> bool {
+        match self {
+            WatchKind::Read => !write,
+            WatchKind::Write => write,
+            WatchKind::ReadWrite => true,
+        }
+    }
+}
+
+/// Watched range of memory word offsets
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Watchpoint {
+    pub offsets: Range<u64>,
+    pub kind: WatchKind,
+}
+
+/// Access that hit a watchpoint during the current instruction
+#[derive(Debug)]
+pub(super) struct WatchHit {
+    pub(super) address: u64,
+    pub(super) old: u64,
+    pub(super) new: u64,
+}
+
+#[derive(Debug, Default)]
+pub(super) struct Watchpoints {
+    list: Vec<Watchpoint>,
+    pub(super) hit: Option<WatchHit>,
+}
+
+impl Watchpoints {
+    /// Cheap check so memory accesses only look further when something is watched
+    #[inline]
+    pub(super) fn is_empty(&self) -> bool {
+        self.list.is_empty()
+    }
+    /// Notes an access to `address`, `old` and `new` are equal for reads
+    pub(super) fn check(&mut self, address: u64, write: bool, old: u64, new: u64) {
+        if self.hit.is_none()
+            && self
+                .list
+                .iter()
+                .any(|w| w.offsets.contains(&address) && w.kind.matches(write))
+        {
+            self.hit = Some(WatchHit { address, old, new });
+        }
+    }
+}
+
+impl Interpreter {
+    /// Stops execution after any instruction accessing memory words in `offsets`
+    /// in a way matching `kind`. A single word is watched with `offset..offset + 1`.
+    pub fn add_watchpoint(&mut self, offsets: Range<u64>, kind: WatchKind) {
+        self.watchpoints.list.push(Watchpoint { offsets, kind });
+        self.update_instrumented();
+    }
+    /// Removes all watchpoints on exactly `offsets`, returning false if there were none
+    pub fn remove_watchpoint(&mut self, offsets: &Range<u64>) -> bool {
+        let before = self.watchpoints.list.len();
+        self.watchpoints.list.retain(|w| &w.offsets != offsets);
+        self.update_instrumented();
+        self.watchpoints.list.len() != before
+    }
+    pub fn clear_watchpoints(&mut self) {
+        self.watchpoints.list.clear();
+        self.update_instrumented();
+    }
+    pub fn watchpoints(&self) -> &[Watchpoint] {
+        &self.watchpoints.list
+    }
+}
@@ -1,4 +1,8 @@
 pub mod assembler;
+pub mod coverage;
+pub mod debugger;
+pub mod disassembler;
 pub mod interpreter;
 pub mod opcode;
 pub mod parser;
+pub mod profiler;
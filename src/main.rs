@@ -1,8 +1,19 @@
 use std::fs;
+use std::io::{BufReader, BufWriter};
 
 use stack_machine::assembler;
-use stack_machine::interpreter::InterpreterEvent;
-use stack_machine::{interpreter::Interpreter, parser::parse_bytes_to_instructions};
+use stack_machine::assembler::source_map::SourceMap;
+use stack_machine::coverage::Coverage;
+use stack_machine::debugger::Debugger;
+use stack_machine::interpreter::{
+    Interpreter, InterpreterConfig, InterpreterError, InterpreterEvent, TraceFilter,
+};
+use stack_machine::opcode::Opcode;
+use stack_machine::parser::{parse_bytes_to_instructions, verify};
+use stack_machine::profiler::{collapsed_stacks, ProfileReport};
+
+/// Steps between full checkpoints of the history kept for `--snapshot-on-error`
+const SNAPSHOT_CHECKPOINT_INTERVAL: usize = 1024;
 
 fn main() {
     let mut args = std::env::args();
@@ -12,43 +23,255 @@ fn main() {
     match mode.as_str() {
         "-A" => {
             let out_path = args.next().expect("No output file path provided");
-            let assembly_txt = fs::read_to_string(file_path).expect("Error loading assembly file");
+            let assembly_txt = fs::read_to_string(&file_path).expect("Error loading assembly file");
             let s = assembler::preprocessor::parse_to_statements(&assembly_txt).unwrap();
             let s = assembler::preprocessor::to_stage2(s).unwrap();
+            let labels = assembler::preprocessor::label_addresses(&s);
             let s = assembler::preprocessor::to_stage3(s).unwrap();
+            let lines = assembler::preprocessor::line_addresses(&s);
+            let source_map = SourceMap::new(labels).with_lines(file_path, lines);
             println!("{:?}", s);
             let compiled = assembler::preprocessor::compile_statements(s).unwrap();
             let assembled = assembler::assemble_string_to_bytes(&compiled);
             println!("{assembled:?}");
+            std::fs::write(SourceMap::path_for(&out_path), source_map.to_string()).unwrap();
             std::fs::write(out_path, assembled).unwrap();
         }
         "-R" => {
-            let bytecode = std::fs::read(file_path).unwrap();
+            let mut config = InterpreterConfig::new();
+            let mut snapshot_on_error = None;
+            let mut trace_path = None;
+            let mut trace_filter = TraceFilter::default();
+            let mut profile_path = None;
+            let mut coverage_path = None;
+            while let Some(flag) = args.next() {
+                let mut value = || -> u64 {
+                    args.next()
+                        .and_then(|v| v.parse().ok())
+                        .unwrap_or_else(|| panic!("{flag} requires a numeric value"))
+                };
+                config = match flag.as_str() {
+                    "--stack-size" => config.stack_size(value() as usize),
+                    "--memory-size" => config.memory_size(value() as usize),
+                    "--fuel" => config.fuel(value()),
+                    "--no-brk" => config.brk_enabled(false),
+                    "--fast" => config.fast_path(true),
+                    "--snapshot-on-error" => {
+                        snapshot_on_error = args.next();
+                        config
+                    }
+                    "--trace" => {
+                        trace_path = args.next();
+                        config
+                    }
+                    "--trace-pc" => {
+                        let range = args
+                            .next()
+                            .and_then(|r| {
+                                let (start, end) = r.split_once("..")?;
+                                Some(start.parse().ok()?..end.parse().ok()?)
+                            })
+                            .unwrap_or_else(|| panic!("{flag} requires a range like 10..20"));
+                        trace_filter.pc_ranges.push(range);
+                        config
+                    }
+                    "--profile" => {
+                        profile_path = args.next();
+                        config
+                    }
+                    "--coverage" => {
+                        coverage_path = args.next();
+                        config
+                    }
+                    "--trace-skip-silent" => {
+                        trace_filter.skip_silent = true;
+                        config
+                    }
+                    _ => panic!("Unknown option {flag}"),
+                };
+            }
+            let bytecode = std::fs::read(&file_path).unwrap();
             let instructions = parse_bytes_to_instructions(&bytecode).unwrap();
             println!("{:?}", instructions);
-            println!("Starting");
-            let start_time = std::time::Instant::now();
-            let mut interpreter = Interpreter::new(instructions);
-            // let mut silent_toggle = false;
-            loop {
-                let res = interpreter.next_instruction();
-                match res {
-                    Ok(InterpreterEvent::ProgramEnd) => break,
-                    // Ok(InterpreterEvent::Silent(s)) => {
-                    //     silent_toggle = s;
-                    // }
-                    Ok(_) => {
-                        // if !silent_toggle {
-                        // println!("Stack: {:?}", interpreter.debug_get_stack());
-                        // }
-                    }
-                    Err(e) => panic!("{e:?}"),
-                }
+            let fast_path = config.fast_path;
+            let mut interpreter = config.build(instructions);
+            if fast_path && !interpreter.is_verified() {
+                println!("Program could not be verified, running with all checks (see -V)");
+            }
+            if let Some(path) = trace_path {
+                let file = fs::File::create(path).expect("Error creating trace file");
+                interpreter.start_trace(BufWriter::new(file), trace_filter);
+            }
+            if profile_path.is_some() || coverage_path.is_some() {
+                interpreter.start_profiling();
+            }
+            let source_map = load_source_map(&file_path);
+            let mut interpreter = run(interpreter, snapshot_on_error, source_map.as_ref());
+            let profile = interpreter.stop_profiling();
+            if let (Some(path), Some(profile)) = (profile_path, &profile) {
+                let report =
+                    ProfileReport::new(profile, interpreter.program(), source_map.as_ref());
+                println!("{report}");
+                fs::write(path, collapsed_stacks(profile, source_map.as_ref()))
+                    .expect("Error writing profile");
+            }
+            if let (Some(path), Some(profile)) = (coverage_path, &profile) {
+                let source_map = source_map.expect("Coverage needs the source map from -A");
+                let source_path = source_map
+                    .source()
+                    .expect("Source map has no source file, assemble again");
+                let source = fs::read_to_string(source_path).expect("Error loading assembly file");
+                let coverage = Coverage::new(profile, &source_map);
+                print!("{}", coverage.annotate(&source));
+                println!(
+                    "{} of {} lines executed",
+                    coverage.lines_hit(),
+                    coverage.lines.len()
+                );
+                let file = fs::File::create(path).expect("Error creating coverage file");
+                coverage
+                    .write_lcov(source_path, &mut BufWriter::new(file))
+                    .expect("Error writing coverage");
+            }
+        }
+        "-D" => {
+            let bytecode = std::fs::read(&file_path).unwrap();
+            let instructions = parse_bytes_to_instructions(&bytecode).unwrap();
+            let source_map = load_source_map(&file_path);
+            let mut debugger = Debugger::new(Interpreter::new(instructions), source_map);
+            debugger
+                .run_repl(std::io::stdin().lock(), std::io::stdout())
+                .unwrap();
+        }
+        "-V" => {
+            let stack_size = match args.next().as_deref() {
+                Some("--stack-size") => args
+                    .next()
+                    .and_then(|v| v.parse().ok())
+                    .expect("--stack-size requires a numeric value"),
+                Some(flag) => panic!("Unknown option {flag}"),
+                None => InterpreterConfig::new().stack_size,
+            };
+            let bytecode = std::fs::read(&file_path).unwrap();
+            let source_map = load_source_map(&file_path);
+            let verification = verify(&bytecode, stack_size);
+            for diagnostic in verification.diagnostics.iter() {
+                let addr = diagnostic.addr as u64;
+                let location = source_map
+                    .as_ref()
+                    .and_then(|m| m.location(addr))
+                    .unwrap_or_else(|| format!("pc {addr}"));
+                println!("{location}: {}", diagnostic.kind);
             }
-            let duration = start_time.elapsed().as_millis();
-            // println!("Memory {:?}", interpreter.debug_get_memory());
-            println!("Took {duration}ms");
+            if !verification.is_safe() {
+                println!("{} problems found", verification.diagnostics.len());
+                std::process::exit(1);
+            }
+            let deepest = verification.depths.values().map(|(_, max)| *max).max();
+            println!(
+                "Verified, stack depth at most {} of {stack_size}",
+                deepest.unwrap_or(0)
+            );
+        }
+        "-S" => {
+            let snapshot_on_error = match args.next().as_deref() {
+                Some("--snapshot-on-error") => args.next(),
+                Some(flag) => panic!("Unknown option {flag}"),
+                None => None,
+            };
+            let file = fs::File::open(file_path).expect("Error opening snapshot");
+            let interpreter = Interpreter::read_snapshot(&mut BufReader::new(file)).unwrap();
+            run(interpreter, snapshot_on_error, None);
         }
         _ => panic!("Unknown mode {}", mode),
     };
 }
+
+/// Loads the source map written next to an assembled file. It is optional, without
+/// it there are no labels.
+fn load_source_map(bytecode_path: &str) -> Option<SourceMap> {
+    fs::read_to_string(SourceMap::path_for(bytecode_path))
+        .ok()
+        .map(|m| SourceMap::parse(&m).expect("Error parsing source map"))
+}
+
+/// `prime.xasm:42: MOD — DivideByZero (:is_prime_loop+12)`, or
+/// `pc 143: MOD — DivideByZero` without a source map, followed by the stack
+fn describe_error(error: &InterpreterError, source_map: Option<&SourceMap>) -> String {
+    let pc = error.pc;
+    let location = source_map
+        .and_then(|m| m.location(pc as u64))
+        .unwrap_or_else(|| format!("pc {pc}"));
+    let opcode = Opcode::from_byte(error.opcode)
+        .map_or_else(|| format!("DB {}", error.opcode), |o| o.to_string());
+    let mut message = format!("{location}: {opcode} — {:?}", error.kind);
+    if let Some(label) = source_map.and_then(|m| m.describe(pc as u64)) {
+        message.push_str(&format!(" ({label})"));
+    }
+    message.push_str(&format!("\nStack: {:?}", error.stack));
+    message
+}
+
+/// Runs the interpreter until the program ends, optionally saving a snapshot if it fails.
+/// Errors are reported against the source when there is a source map.
+fn run(
+    mut interpreter: Interpreter,
+    snapshot_on_error: Option<String>,
+    source_map: Option<&SourceMap>,
+) -> Interpreter {
+    if snapshot_on_error.is_some() {
+        // Keep the last step, so a failure is saved from before the failing instruction
+        interpreter.start_recording(1, SNAPSHOT_CHECKPOINT_INTERVAL);
+    }
+    println!("Starting");
+    let start_time = std::time::Instant::now();
+    // let mut silent_toggle = false;
+    loop {
+        let res = interpreter.next_instruction();
+        match res {
+            Ok(InterpreterEvent::ProgramEnd) => break,
+            Ok(InterpreterEvent::OutOfFuel) => {
+                interpreter.flush_output().unwrap();
+                let pc = interpreter.program_counter();
+                let location = source_map
+                    .and_then(|m| m.location(pc as u64))
+                    .unwrap_or_else(|| format!("pc {pc}"));
+                let start = interpreter.config().fuel.unwrap_or(0);
+                let used = start - interpreter.fuel().unwrap_or(0);
+                eprintln!("{location}: ran out of fuel after using {used} of {start}");
+                std::process::exit(2);
+            }
+            Ok(InterpreterEvent::Breakpoint { pc }) => {
+                interpreter.flush_output().unwrap();
+                println!("BRK at {pc}, stack: {:?}", interpreter.debug_get_stack());
+            }
+            // Ok(InterpreterEvent::Silent(s)) => {
+            //     silent_toggle = s;
+            // }
+            Ok(_) => {
+                // if !silent_toggle {
+                // println!("Stack: {:?}", interpreter.debug_get_stack());
+                // }
+            }
+            Err(e) => {
+                if let Some(path) = snapshot_on_error {
+                    interpreter.step_back();
+                    interpreter.stop_recording();
+                    let file = fs::File::create(&path).expect("Error creating snapshot");
+                    interpreter
+                        .write_snapshot(&mut BufWriter::new(file))
+                        .expect("Error writing snapshot");
+                    println!("Saved snapshot to {path}");
+                }
+                // The error may be from writing output, which is then lost anyway
+                interpreter.flush_output().ok();
+                eprintln!("{}", describe_error(&e, source_map));
+                std::process::exit(1);
+            }
+        }
+    }
+    let duration = start_time.elapsed().as_millis();
+    // println!("Memory {:?}", interpreter.debug_get_memory());
+    println!("Took {duration}ms");
+    interpreter
+}
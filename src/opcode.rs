@@ -26,6 +26,24 @@ pub enum Opcode {
     Dup3 = 20,
     Dup4 = 21,
     GotoTarget = 22,
+    /// Push the address after this instruction onto the return stack and goto
+    Call = 23,
+    /// Return to the address on top of the return stack
+    Ret = 24,
+    /// Pop n, then push a copy of the nth item from the top (0 is the top)
+    Pick = 25,
+    /// Pop n, then move the nth item from the top to the top
+    Roll = 26,
+    /// Move the third item from the top to the top, same as ROLL 2
+    Rot = 27,
+    /// Remove the second item from the top
+    Nip = 28,
+    /// Copy the top item below the second item
+    Tuck = 29,
+    /// Pop two items
+    Drop2 = 30,
+    /// Push the number of items on the stack
+    Depth = 31,
 
     Push0 = 32,
     Push1 = 33,
@@ -37,9 +55,97 @@ pub enum Opcode {
     Push7 = 39,
     Push8 = 40,
 
+    /// Pop an address to jump to, with the error code pushed, when an instruction fails.
+    /// Errors with a full stack, where the code does not fit, are not caught.
+    SetTrap = 41,
+    /// Remove the trap handler, errors end execution again
+    ClearTrap = 42,
+
+    // Signed integer instructions, operating on words as two's complement i64
+    SDiv = 48,
+    SMod = 49,
+    SLt = 50,
+    SGt = 51,
+    Neg = 52,
+    Abs = 53,
+    /// Sign extend the lowest byte to a full word
+    SignExtend8 = 54,
+    /// Sign extend the lowest 2 bytes to a full word
+    SignExtend16 = 55,
+    /// Sign extend the lowest 4 bytes to a full word
+    SignExtend32 = 56,
+
+    // Bitwise instructions. Shifts and rotates take the value from the top of
+    // the stack and the count from the item below it.
+    And = 64,
+    Or = 65,
+    Xor = 66,
+    /// Shift left, counts of 64 or more produce 0
+    Shl = 67,
+    /// Logical shift right, counts of 64 or more produce 0
+    Shr = 68,
+    /// Arithmetic shift right, counts of 64 or more fill the word with the sign bit
+    Sar = 69,
+    /// Rotate left, the count is taken modulo 64
+    RotL = 70,
+    /// Rotate right, the count is taken modulo 64
+    RotR = 71,
+    /// Number of set bits
+    PopCnt = 72,
+    /// Number of leading zero bits, 64 for a zero word
+    Clz = 73,
+    /// Number of trailing zero bits, 64 for a zero word
+    Ctz = 74,
+
+    // Floating point instructions, operating on words as IEEE-754 f64 bits
+    FAdd = 80,
+    FSub = 81,
+    FMul = 82,
+    FDiv = 83,
+    FSqrt = 84,
+    FLt = 85,
+    FEq = 86,
+    /// Convert a signed integer to a float
+    IToF = 87,
+    /// Convert a float to a signed integer, truncating towards zero and saturating
+    FToI = 88,
+
+    // Sub-word memory instructions. These use byte addresses over the same memory
+    // as MLOAD/MSTORE: byte address `n` is byte `n % 8` (little endian) of word `n / 8`.
+    // Addresses must be aligned to the access width.
+    /// Load a byte, zero extended
+    MemLoad8 = 96,
+    /// Load 2 bytes, zero extended
+    MemLoad16 = 97,
+    /// Load 4 bytes, zero extended
+    MemLoad32 = 98,
+    /// Load a byte, sign extended
+    MemLoad8S = 99,
+    /// Load 2 bytes, sign extended
+    MemLoad16S = 100,
+    /// Load 4 bytes, sign extended
+    MemLoad32S = 101,
+    /// Store the lowest byte
+    MemStore8 = 102,
+    /// Store the lowest 2 bytes
+    MemStore16 = 103,
+    /// Store the lowest 4 bytes
+    MemStore32 = 104,
+
     NoOp = 128,
+    /// Pop a host function id and call the Rust function registered with it
+    HostCall = 129,
+    /// Push the next byte of input, or u64::MAX at the end of input
+    ReadChar = 130,
+    /// Read a line of input and push it parsed as a decimal u64, or u64::MAX at the end of input.
+    /// u64::MAX itself is not accepted as input.
+    ReadNum = 131,
 
     // Debugging instructions
+    /// Breakpoint, stops execution with an event when enabled
+    Brk = 251,
+    /// Print top of stack as a float to debug stdout
+    DebugFloat = 252,
     DbgSilent = 253,
     /// Print top of stack to debug stdout
     Debug = 255,
@@ -53,6 +159,21 @@ impl From<Opcode> for u8 {
     }
 }
 impl Opcode {
+    /// Number of immediate parameter bytes following the opcode in bytecode
+    pub fn immediate_size(&self) -> usize {
+        use Opcode::*;
+        match self {
+            Push1 => 1,
+            Push2 => 2,
+            Push3 => 3,
+            Push4 => 4,
+            Push5 => 5,
+            Push6 => 6,
+            Push7 => 7,
+            Push8 => 8,
+            _ => 0,
+        }
+    }
     pub fn from_byte(input: u8) -> Option<Opcode> {
         use Opcode::*;
         Some(match input {
@@ -78,6 +199,15 @@ impl Opcode {
             20 => Dup3,
             21 => Dup4,
             22 => GotoTarget,
+            23 => Call,
+            24 => Ret,
+            25 => Pick,
+            26 => Roll,
+            27 => Rot,
+            28 => Nip,
+            29 => Tuck,
+            30 => Drop2,
+            31 => Depth,
             32 => Push0,
             33 => Push1,
             34 => Push2,
@@ -87,8 +217,53 @@ impl Opcode {
             38 => Push6,
             39 => Push7,
             40 => Push8,
+            41 => SetTrap,
+            42 => ClearTrap,
+            48 => SDiv,
+            49 => SMod,
+            50 => SLt,
+            51 => SGt,
+            52 => Neg,
+            53 => Abs,
+            54 => SignExtend8,
+            55 => SignExtend16,
+            56 => SignExtend32,
+            64 => And,
+            65 => Or,
+            66 => Xor,
+            67 => Shl,
+            68 => Shr,
+            69 => Sar,
+            70 => RotL,
+            71 => RotR,
+            72 => PopCnt,
+            73 => Clz,
+            74 => Ctz,
+            80 => FAdd,
+            81 => FSub,
+            82 => FMul,
+            83 => FDiv,
+            84 => FSqrt,
+            85 => FLt,
+            86 => FEq,
+            87 => IToF,
+            88 => FToI,
+            96 => MemLoad8,
+            97 => MemLoad16,
+            98 => MemLoad32,
+            99 => MemLoad8S,
+            100 => MemLoad16S,
+            101 => MemLoad32S,
+            102 => MemStore8,
+            103 => MemStore16,
+            104 => MemStore32,
 
             128 => NoOp,
+            129 => HostCall,
+            130 => ReadChar,
+            131 => ReadNum,
+            251 => Brk,
+            252 => DebugFloat,
             253 => DbgSilent,
             254 => DebugChar,
             255 => Debug,
@@ -130,13 +305,67 @@ impl TryInto<Opcode> for &str {
             "MSTORE" => MemStore,
             "GOTONZ" => GotoNz,
             "GOTOTARGET" => GotoTarget,
+            "CALL" => Call,
+            "RET" => Ret,
+            "SETTRAP" => SetTrap,
+            "CLEARTRAP" => ClearTrap,
+            "PICK" => Pick,
+            "ROLL" => Roll,
+            "ROT" => Rot,
+            "NIP" => Nip,
+            "TUCK" => Tuck,
+            "DROP2" => Drop2,
+            "DEPTH" => Depth,
             "EQ" => Eq,
             "LT" => Lt,
             "GT" => Gt,
             "NOT" => Not,
+            "SDIV" => SDiv,
+            "SMOD" => SMod,
+            "SLT" => SLt,
+            "SGT" => SGt,
+            "NEG" => Neg,
+            "ABS" => Abs,
+            "SEXT8" => SignExtend8,
+            "SEXT16" => SignExtend16,
+            "SEXT32" => SignExtend32,
+            "AND" => And,
+            "OR" => Or,
+            "XOR" => Xor,
+            "SHL" => Shl,
+            "SHR" => Shr,
+            "SAR" => Sar,
+            "ROTL" => RotL,
+            "ROTR" => RotR,
+            "POPCNT" => PopCnt,
+            "CLZ" => Clz,
+            "CTZ" => Ctz,
+            "FADD" => FAdd,
+            "FSUB" => FSub,
+            "FMUL" => FMul,
+            "FDIV" => FDiv,
+            "FSQRT" => FSqrt,
+            "FLT" => FLt,
+            "FEQ" => FEq,
+            "ITOF" => IToF,
+            "FTOI" => FToI,
+            "MLOAD8" => MemLoad8,
+            "MLOAD16" => MemLoad16,
+            "MLOAD32" => MemLoad32,
+            "MLOAD8S" => MemLoad8S,
+            "MLOAD16S" => MemLoad16S,
+            "MLOAD32S" => MemLoad32S,
+            "MSTORE8" => MemStore8,
+            "MSTORE16" => MemStore16,
+            "MSTORE32" => MemStore32,
             "NOOP" => NoOp,
+            "HOSTCALL" => HostCall,
+            "READCHAR" => ReadChar,
+            "READNUM" => ReadNum,
             "DEBUG" => Debug,
             "DEBUGCHAR" => DebugChar,
+            "DEBUGFLOAT" => DebugFloat,
+            "BRK" => Brk,
             "DBGSILENT" => DbgSilent,
             _ => return Err(format!("Unknown Opcode {}", self)),
         })
@@ -177,10 +406,64 @@ impl Display for Opcode {
             Lt => "LT",
             Gt => "GT",
             Not => "NOT",
+            SDiv => "SDIV",
+            SMod => "SMOD",
+            SLt => "SLT",
+            SGt => "SGT",
+            Neg => "NEG",
+            Abs => "ABS",
+            SignExtend8 => "SEXT8",
+            SignExtend16 => "SEXT16",
+            SignExtend32 => "SEXT32",
+            And => "AND",
+            Or => "OR",
+            Xor => "XOR",
+            Shl => "SHL",
+            Shr => "SHR",
+            Sar => "SAR",
+            RotL => "ROTL",
+            RotR => "ROTR",
+            PopCnt => "POPCNT",
+            Clz => "CLZ",
+            Ctz => "CTZ",
+            FAdd => "FADD",
+            FSub => "FSUB",
+            FMul => "FMUL",
+            FDiv => "FDIV",
+            FSqrt => "FSQRT",
+            FLt => "FLT",
+            FEq => "FEQ",
+            IToF => "ITOF",
+            FToI => "FTOI",
+            MemLoad8 => "MLOAD8",
+            MemLoad16 => "MLOAD16",
+            MemLoad32 => "MLOAD32",
+            MemLoad8S => "MLOAD8S",
+            MemLoad16S => "MLOAD16S",
+            MemLoad32S => "MLOAD32S",
+            MemStore8 => "MSTORE8",
+            MemStore16 => "MSTORE16",
+            MemStore32 => "MSTORE32",
             NoOp => "NOOP",
+            HostCall => "HOSTCALL",
+            ReadChar => "READCHAR",
+            ReadNum => "READNUM",
             Debug => "DEBUG",
             DebugChar => "DEBUGCHAR",
+            DebugFloat => "DEBUGFLOAT",
+            Brk => "BRK",
             GotoTarget => "GOTOTARGET",
+            Call => "CALL",
+            Ret => "RET",
+            SetTrap => "SETTRAP",
+            ClearTrap => "CLEARTRAP",
+            Pick => "PICK",
+            Roll => "ROLL",
+            Rot => "ROT",
+            Nip => "NIP",
+            Tuck => "TUCK",
+            Drop2 => "DROP2",
+            Depth => "DEPTH",
             DbgSilent => "DBGSILENT",
         };
         write!(f, "{s}")
@@ -1,43 +1,72 @@
 use crate::opcode::Opcode;
 
+pub mod verifier;
+
+pub use verifier::{verify, Diagnostic, DiagnosticKind, Verification};
+
 #[derive(Debug)]
 pub enum ParseError {
     PushParameterReachedEnd,
     NeverHalts,
+    /// Byte that is not an opcode, with its address
+    UnknownOpcode { addr: usize, byte: u8 },
+}
+
+/// An instruction decoded from bytecode
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DecodedInstruction {
+    pub addr: usize,
+    pub byte: u8,
+    /// `None` if the byte is not an opcode, which then decodes as a single byte
+    pub opcode: Option<Opcode>,
+    /// Immediate parameter zero extended to a word, `None` if the opcode takes none
+    pub immediate: Option<u64>,
+    /// Length in bytes, including the immediate parameter
+    pub len: usize,
 }
 
+/// Decodes the instruction at `addr`. Returns `None` past the end of the program,
+/// and an error if its immediate parameter runs past the end.
+pub fn decode_instruction(
+    program: &[u8],
+    addr: usize,
+) -> Option<Result<DecodedInstruction, ParseError>> {
+    let byte = *program.get(addr)?;
+    let opcode = Opcode::from_byte(byte);
+    let size = opcode.map_or(0, |o| o.immediate_size());
+    let Some(parameter) = program.get(addr + 1..addr + 1 + size) else {
+        return Some(Err(ParseError::PushParameterReachedEnd));
+    };
+    let immediate = (size > 0).then(|| {
+        let mut buffer = [0; 8];
+        buffer[..size].copy_from_slice(parameter);
+        u64::from_le_bytes(buffer)
+    });
+    Some(Ok(DecodedInstruction {
+        addr,
+        byte,
+        opcode,
+        immediate,
+        len: size + 1,
+    }))
+}
+
+/// Checks that a program decodes and ends with HALT. [`verify`] checks what it does.
 pub fn parse_bytes_to_instructions(bytes: &[u8]) -> Result<Vec<u8>, ParseError> {
-    // Ensure input is valid
-    let mut iter = bytes.iter().cloned();
-    while let Some(next) = iter.next() {
-        let opcode: Opcode = Opcode::from_byte(next).expect("Unknown Opcode while parsing");
-        match opcode {
-            Opcode::Push8 => {
-                for _ in 0..8 {
-                    iter.next().ok_or(ParseError::PushParameterReachedEnd)?;
-                }
-            }
-            Opcode::Push1 => {
-                iter.next().ok_or(ParseError::PushParameterReachedEnd)?;
-            }
-            Opcode::Push2 => {
-                for _ in 0..2 {
-                    iter.next().ok_or(ParseError::PushParameterReachedEnd)?;
-                }
-            }
-            Opcode::Push3 | Opcode::Push4 | Opcode::Push5 | Opcode::Push6 | Opcode::Push7 => {
-                todo!()
-            }
-            _ => (),
+    let mut addr = 0;
+    while let Some(instruction) = decode_instruction(bytes, addr) {
+        let instruction = instruction?;
+        if instruction.opcode.is_none() {
+            return Err(ParseError::UnknownOpcode {
+                addr,
+                byte: instruction.byte,
+            });
         }
+        addr += instruction.len;
     }
 
-    if let Some(v) = bytes.last() {
-        if *v != Opcode::Halt.into() {
-            return Err(ParseError::PushParameterReachedEnd);
-        }
-    } else {
-        return Err(ParseError::PushParameterReachedEnd);
+    if bytes.last() != Some(&Opcode::Halt.into()) {
+        return Err(ParseError::NeverHalts);
     }
 
     Ok(bytes.to_owned())
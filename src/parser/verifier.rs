@@ -0,0 +1,429 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Display;
+
+use super::decode_instruction;
+use crate::opcode::Opcode;
+
+/// Updates of one instruction's depth range before it is widened to the whole stack,
+/// so that loops growing or shrinking the stack settle quickly
+const WIDEN_AFTER: usize = 16;
+
+/// Something the verifier could not prove about a program
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum DiagnosticKind {
+    /// Byte that is not an opcode, executed as a no-op
+    UnknownOpcode(u8),
+    /// PUSH whose immediate runs past the end of the program
+    TruncatedImmediate,
+    /// The last byte of the program is not HALT
+    MissingHalt,
+    /// GOTO, GOTONZ, CALL or SETTRAP to an address that is not a GOTOTARGET
+    /// instruction. A GOTOTARGET byte inside a PUSH immediate does not count.
+    InvalidJumpTarget(u64),
+    /// GOTO, GOTONZ, CALL or SETTRAP whose address is not pushed right before it
+    DynamicJump,
+    /// PICK or ROLL whose depth is not pushed right before it
+    DynamicDepth,
+    /// HOSTCALL, which can change the stack in any way. Nothing after it is checked.
+    HostCall,
+    /// The stack may have fewer items than the instruction needs
+    StackUnderflow { needs: usize },
+    /// The instruction may push past the end of the stack
+    StackOverflow,
+}
+
+impl Display for DiagnosticKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        use DiagnosticKind::*;
+        match self {
+            UnknownOpcode(byte) => write!(f, "unknown opcode {byte}"),
+            TruncatedImmediate => write!(f, "immediate runs past the end of the program"),
+            MissingHalt => write!(f, "program does not end with HALT"),
+            InvalidJumpTarget(addr) => write!(f, "address {addr} is not a GOTOTARGET"),
+            DynamicJump => write!(f, "jump address is not a constant"),
+            DynamicDepth => write!(f, "stack depth is not a constant"),
+            HostCall => write!(f, "host call has an unknown stack effect"),
+            StackUnderflow { needs } => write!(f, "stack may have fewer than {needs} items"),
+            StackOverflow => write!(f, "stack may overflow"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Diagnostic {
+    /// Address of the instruction the diagnostic is about
+    pub addr: usize,
+    pub kind: DiagnosticKind,
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.addr, self.kind)
+    }
+}
+
+/// What the verifier found out about a program
+#[derive(Debug, Clone, PartialEq)]
+pub struct Verification {
+    /// In address order
+    pub diagnostics: Vec<Diagnostic>,
+    /// Smallest and largest stack depth before each reachable instruction, by address
+    pub depths: BTreeMap<usize, (usize, usize)>,
+}
+
+impl Verification {
+    /// Whether the program is proven to never underflow or overflow the stack, and to
+    /// only jump to GOTOTARGETs
+    pub fn is_safe(&self) -> bool {
+        self.diagnostics.is_empty()
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Instruction {
+    pub(crate) addr: usize,
+    pub(crate) opcode: Option<Opcode>,
+    /// Value pushed by a PUSH
+    pub(crate) constant: Option<u64>,
+}
+
+/// Edges of the control-flow graph leaving an instruction, as instruction indices
+#[derive(Debug, Default)]
+struct Node {
+    successors: Vec<usize>,
+    /// Handler installed by a SETTRAP
+    trap_handler: Option<usize>,
+}
+
+/// Decodes the whole program, noting unknown opcodes, a truncated immediate and a
+/// missing HALT
+pub(crate) fn decode(program: &[u8], diagnostics: &mut BTreeSet<Diagnostic>) -> Vec<Instruction> {
+    let mut instructions = Vec::new();
+    let mut addr = 0;
+    while let Some(decoded) = decode_instruction(program, addr) {
+        let Ok(decoded) = decoded else {
+            diagnostics.insert(Diagnostic {
+                addr,
+                kind: DiagnosticKind::TruncatedImmediate,
+            });
+            break;
+        };
+        if decoded.opcode.is_none() {
+            diagnostics.insert(Diagnostic {
+                addr,
+                kind: DiagnosticKind::UnknownOpcode(decoded.byte),
+            });
+        }
+        let constant = match decoded.opcode {
+            Some(Opcode::Push0) => Some(0),
+            _ => decoded.immediate,
+        };
+        instructions.push(Instruction {
+            addr,
+            opcode: decoded.opcode,
+            constant,
+        });
+        addr += decoded.len;
+    }
+    if program.last() != Some(&Opcode::Halt.into()) {
+        diagnostics.insert(Diagnostic {
+            addr: program.len().saturating_sub(1),
+            kind: DiagnosticKind::MissingHalt,
+        });
+    }
+    instructions
+}
+
+/// Items the instruction needs on the stack, and how it changes the depth. `operand`
+/// is the constant pushed right before it, if any.
+fn stack_effect(opcode: Option<Opcode>, operand: Option<u64>) -> (usize, isize) {
+    use Opcode::*;
+    let Some(opcode) = opcode else {
+        return (0, 0);
+    };
+    match opcode {
+        Halt | GotoTarget | Ret | ClearTrap | NoOp | Brk | DbgSilent => (0, 0),
+        Push0 | Push1 | Push2 | Push3 | Push4 | Push5 | Push6 | Push7 | Push8 | Depth
+        | ReadChar | ReadNum => (0, 1),
+        Pop | Goto | Call | SetTrap | DebugChar => (1, -1),
+        Not | MemLoad | Neg | Abs | SignExtend8 | SignExtend16 | SignExtend32 | PopCnt | Clz
+        | Ctz | FSqrt | IToF | FToI | MemLoad8 | MemLoad16 | MemLoad32 | MemLoad8S
+        | MemLoad16S | MemLoad32S | Debug | DebugFloat => (1, 0),
+        Dup => (1, 1),
+        Add | Sub | Mul | Div | Mod | Eq | Lt | Gt | Nip | SDiv | SMod | SLt | SGt | And | Or
+        | Xor | Shl | Shr | Sar | RotL | RotR | FAdd | FSub | FMul | FDiv | FLt | FEq => (2, -1),
+        Swap => (2, 0),
+        Dup2 | Tuck => (2, 1),
+        MemStore | GotoNz | Drop2 | MemStore8 | MemStore16 | MemStore32 => (2, -2),
+        Swap2 | Rot => (3, 0),
+        Dup3 => (3, 1),
+        Dup4 => (4, 1),
+        // The depth operand and the n + 1 items it reaches
+        Pick => (operand.map_or(1, |n| (n as usize).saturating_add(2)), 0),
+        Roll => (operand.map_or(1, |n| (n as usize).saturating_add(2)), -1),
+        // Only the id is known to be popped
+        HostCall => (1, -1),
+    }
+}
+
+/// Builds the control-flow graph. Jump addresses are only known when pushed by the
+/// instruction right before the jump, which is how the assembler compiles labels.
+fn build_graph(instructions: &[Instruction], diagnostics: &mut BTreeSet<Diagnostic>) -> Vec<Node> {
+    let index_of: BTreeMap<usize, usize> = instructions
+        .iter()
+        .enumerate()
+        .map(|(index, ins)| (ins.addr, index))
+        .collect();
+    let mut nodes: Vec<Node> = instructions.iter().map(|_| Node::default()).collect();
+    let mut return_sites = Vec::new();
+    let mut returns = Vec::new();
+    for (index, ins) in instructions.iter().enumerate() {
+        let mut diagnostic = |kind| {
+            diagnostics.insert(Diagnostic {
+                addr: ins.addr,
+                kind,
+            });
+        };
+        let operand = index.checked_sub(1).and_then(|i| instructions[i].constant);
+        let next = (index + 1 < instructions.len()).then_some(index + 1);
+        let opcode = ins.opcode;
+        let mut target = || match operand {
+            None => {
+                diagnostic(DiagnosticKind::DynamicJump);
+                None
+            }
+            Some(addr) => {
+                let target = index_of
+                    .get(&(addr as usize))
+                    .copied()
+                    .filter(|t| instructions[*t].opcode == Some(Opcode::GotoTarget));
+                if target.is_none() {
+                    diagnostic(DiagnosticKind::InvalidJumpTarget(addr));
+                }
+                target
+            }
+        };
+        let node = &mut nodes[index];
+        match opcode {
+            Some(Opcode::Halt) => (),
+            Some(Opcode::Goto) => node.successors.extend(target()),
+            Some(Opcode::GotoNz) => node.successors.extend(target().into_iter().chain(next)),
+            Some(Opcode::Call) => {
+                node.successors.extend(target());
+                return_sites.extend(next);
+            }
+            Some(Opcode::Ret) => returns.push(index),
+            Some(Opcode::SetTrap) => {
+                node.trap_handler = target();
+                node.successors.extend(next);
+            }
+            Some(Opcode::HostCall) => diagnostic(DiagnosticKind::HostCall),
+            Some(Opcode::Pick | Opcode::Roll) => {
+                if operand.is_none() {
+                    diagnostic(DiagnosticKind::DynamicDepth);
+                }
+                node.successors.extend(next);
+            }
+            _ => node.successors.extend(next),
+        }
+    }
+    // Calls are not matched with their returns, any RET may go back after any CALL
+    for index in returns {
+        nodes[index].successors = return_sites.clone();
+    }
+    nodes
+}
+
+/// Stack depth ranges found so far, and the instructions to look at again
+struct Depths {
+    ranges: Vec<Option<(usize, usize)>>,
+    updates: Vec<usize>,
+    worklist: Vec<usize>,
+    stack_size: usize,
+}
+
+impl Depths {
+    /// Merges a range reaching an instruction into its known range
+    fn join(&mut self, index: usize, (min, max): (usize, usize)) {
+        let joined = match self.ranges[index] {
+            None => (min, max),
+            Some((old_min, old_max)) => {
+                let mut joined = (old_min.min(min), old_max.max(max));
+                if joined == (old_min, old_max) {
+                    return;
+                }
+                self.updates[index] += 1;
+                if self.updates[index] > WIDEN_AFTER {
+                    if joined.0 < old_min {
+                        joined.0 = 0;
+                    }
+                    if joined.1 > old_max {
+                        joined.1 = self.stack_size;
+                    }
+                }
+                joined
+            }
+        };
+        self.ranges[index] = Some(joined);
+        self.worklist.push(index);
+    }
+}
+
+/// Checks a program that will run with a stack of `stack_size` items.
+///
+/// Stack depths are tracked as a range per instruction over every path through the
+/// control-flow graph, so a diagnostic means some path may fail, not that one will.
+pub fn verify(program: &[u8], stack_size: usize) -> Verification {
+    let mut diagnostics = BTreeSet::new();
+    let instructions = decode(program, &mut diagnostics);
+    let nodes = build_graph(&instructions, &mut diagnostics);
+
+    let mut depths = Depths {
+        ranges: vec![None; instructions.len()],
+        updates: vec![0; instructions.len()],
+        worklist: Vec::new(),
+        stack_size,
+    };
+    if !instructions.is_empty() {
+        depths.join(0, (0, 0));
+    }
+    while let Some(index) = depths.worklist.pop() {
+        let ins = instructions[index];
+        let (min, max) = depths.ranges[index].unwrap();
+        let operand = index.checked_sub(1).and_then(|i| instructions[i].constant);
+        let (needs, delta) = stack_effect(ins.opcode, operand);
+        if min < needs {
+            diagnostics.insert(Diagnostic {
+                addr: ins.addr,
+                kind: DiagnosticKind::StackUnderflow { needs },
+            });
+        }
+        if max < needs || ins.opcode == Some(Opcode::HostCall) {
+            continue;
+        }
+        let after_min = (min.max(needs) as isize + delta) as usize;
+        let mut after_max = (max as isize + delta) as usize;
+        if after_max > stack_size {
+            diagnostics.insert(Diagnostic {
+                addr: ins.addr,
+                kind: DiagnosticKind::StackOverflow,
+            });
+            if after_min > stack_size {
+                continue;
+            }
+            after_max = stack_size;
+        }
+        for &successor in nodes[index].successors.iter() {
+            depths.join(successor, (after_min, after_max));
+        }
+        if let Some(handler) = nodes[index].trap_handler {
+            // The handler can be entered from anywhere, with the error code pushed
+            depths.join(handler, (1, stack_size.max(1)));
+        }
+    }
+
+    Verification {
+        diagnostics: diagnostics.into_iter().collect(),
+        depths: instructions
+            .iter()
+            .zip(depths.ranges)
+            .filter_map(|(ins, depth)| Some((ins.addr, depth?)))
+            .collect(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{verify, Diagnostic, DiagnosticKind};
+    use crate::assembler::assemble;
+
+    fn kinds(source: &str, stack_size: usize) -> Vec<DiagnosticKind> {
+        verify(&assemble(source).unwrap(), stack_size)
+            .diagnostics
+            .into_iter()
+            .map(|d| d.kind)
+            .collect()
+    }
+
+    #[test]
+    fn balanced_loops_and_calls_are_safe() {
+        let program = assemble(
+            r#"
+PUSH 3
+:loop
+CALL :double_dec
+DUP
+GOTONZ :loop
+POP
+HALT
+:double_dec
+DUP
+ADD
+DEC
+RET
+HALT
+"#,
+        )
+        .unwrap();
+        let verification = verify(&program, 4);
+        assert_eq!(verification.diagnostics, []);
+        assert!(verification.is_safe());
+        // PUSH1 3 at 0, GOTOTARGET at 2, the loop body is entered with one item
+        assert_eq!(verification.depths[&0], (0, 0));
+        assert_eq!(verification.depths[&2], (1, 1));
+        // The last HALT is never reached
+        assert!(!verification.depths.contains_key(&(program.len() - 1)));
+    }
+
+    #[test]
+    fn finds_unsafe_programs() {
+        assert_eq!(
+            verify(&assemble("PUSH 1\nGOTO\nHALT").unwrap(), 4).diagnostics,
+            [Diagnostic {
+                addr: 2,
+                kind: DiagnosticKind::InvalidJumpTarget(1)
+            }]
+        );
+        assert_eq!(
+            kinds("PUSH 1\nADD\nHALT", 4),
+            [DiagnosticKind::StackUnderflow { needs: 2 }]
+        );
+        assert_eq!(
+            kinds(":grow\nPUSH 1\nGOTO :grow\nHALT", 4),
+            [DiagnosticKind::StackOverflow]
+        );
+        assert_eq!(
+            kinds("PUSH 1\nSWAP\nGOTO\nHALT", 4),
+            [
+                DiagnosticKind::StackUnderflow { needs: 2 },
+                DiagnosticKind::DynamicJump
+            ]
+        );
+        // The trap handler is entered with the error code and possibly nothing else
+        assert_eq!(
+            kinds("SETTRAP :handler\nHALT\n:handler\nDROP2\nHALT", 4),
+            [DiagnosticKind::StackUnderflow { needs: 2 }]
+        );
+        assert_eq!(
+            kinds("HOSTCALL 7\nPOP\nHALT", 4),
+            [DiagnosticKind::HostCall]
+        );
+        assert_eq!(
+            verify(&[0, 33], 4).diagnostics,
+            [
+                Diagnostic {
+                    addr: 0,
+                    kind: DiagnosticKind::UnknownOpcode(0)
+                },
+                Diagnostic {
+                    addr: 1,
+                    kind: DiagnosticKind::TruncatedImmediate
+                },
+                Diagnostic {
+                    addr: 1,
+                    kind: DiagnosticKind::MissingHalt
+                }
+            ]
+        );
+    }
+}
@@ -0,0 +1,130 @@
+use std::collections::HashMap;
+use std::fmt::Display;
+
+use crate::assembler::source_map::SourceMap;
+use crate::interpreter::Profile;
+use crate::opcode::Opcode;
+
+/// Rows shown per section of the hot-spot table
+const TABLE_ROWS: usize = 15;
+
+/// Name of the code an address belongs to, its nearest label
+fn frame_name(source_map: Option<&SourceMap>, addr: usize) -> String {
+    source_map
+        .and_then(|m| m.nearest_label(addr as u64))
+        .map_or_else(|| "<entry>".to_string(), |(_, label)| label.to_string())
+}
+
+/// Sorts counts with the highest first, ties by name
+fn sorted<K: Ord>(counts: HashMap<K, u64>) -> Vec<(K, u64)> {
+    let mut counts: Vec<_> = counts.into_iter().collect();
+    counts.sort_by(|(ka, a), (kb, b)| b.cmp(a).then(ka.cmp(kb)));
+    counts
+}
+
+/// Execution counts of a profiled run folded by address, opcode and label
+#[derive(Debug)]
+pub struct ProfileReport {
+    pub total: u64,
+    /// Highest count first
+    pub by_label: Vec<(String, u64)>,
+    pub by_opcode: Vec<(String, u64)>,
+    pub by_address: Vec<(usize, u64)>,
+}
+
+impl ProfileReport {
+    pub fn new(profile: &Profile, program: &[u8], source_map: Option<&SourceMap>) -> ProfileReport {
+        let mut by_label = HashMap::new();
+        let mut by_opcode = HashMap::new();
+        let mut by_address = HashMap::new();
+        for (addr, &count) in profile.counts().iter().enumerate() {
+            if count == 0 {
+                continue;
+            }
+            *by_label.entry(frame_name(source_map, addr)).or_insert(0) += count;
+            let opcode = Opcode::from_byte(program[addr])
+                .map_or_else(|| format!("DB {}", program[addr]), |o| o.to_string());
+            *by_opcode.entry(opcode).or_insert(0) += count;
+            by_address.insert(addr, count);
+        }
+        ProfileReport {
+            total: profile.total(),
+            by_label: sorted(by_label),
+            by_opcode: sorted(by_opcode),
+            by_address: sorted(by_address),
+        }
+    }
+}
+
+impl Display for ProfileReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "{} instructions executed", self.total)?;
+        let percent = |count: u64| count as f64 * 100.0 / self.total.max(1) as f64;
+        writeln!(f, "\n{:>12} {:>7}  label", "count", "%")?;
+        for (label, count) in self.by_label.iter().take(TABLE_ROWS) {
+            writeln!(f, "{count:>12} {:>6.2}%  {label}", percent(*count))?;
+        }
+        writeln!(f, "\n{:>12} {:>7}  opcode", "count", "%")?;
+        for (opcode, count) in self.by_opcode.iter().take(TABLE_ROWS) {
+            writeln!(f, "{count:>12} {:>6.2}%  {opcode}", percent(*count))?;
+        }
+        writeln!(f, "\n{:>12} {:>7}  address", "count", "%")?;
+        for (addr, count) in self.by_address.iter().take(TABLE_ROWS) {
+            writeln!(f, "{count:>12} {:>6.2}%  {addr}", percent(*count))?;
+        }
+        Ok(())
+    }
+}
+
+/// Counts in the collapsed stack format read by flamegraph tools, one
+/// `outer;inner;current count` line per distinct stack of labels
+pub fn collapsed_stacks(profile: &Profile, source_map: Option<&SourceMap>) -> String {
+    let mut stacks = HashMap::new();
+    for (calls, pc, count) in profile.stacks() {
+        let frames: Vec<String> = calls
+            .iter()
+            .chain([&pc])
+            .map(|addr| frame_name(source_map, *addr))
+            .collect();
+        *stacks.entry(frames.join(";")).or_insert(0) += count;
+    }
+    let mut stacks: Vec<_> = stacks.into_iter().collect();
+    stacks.sort();
+    stacks
+        .into_iter()
+        .map(|(stack, count)| format!("{stack} {count}\n"))
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::{collapsed_stacks, ProfileReport};
+    use crate::assembler::assemble_with_map;
+    use crate::interpreter::{Interpreter, InterpreterEvent};
+
+    #[test]
+    fn folds_by_label() {
+        let source = ":main\nPUSH 2\nCALL :double\nCALL :double\nHALT\n:double\nDUP\nADD\nRET";
+        let (program, map) = assemble_with_map(source, "double.xasm").unwrap();
+
+        let mut i = Interpreter::new(program.clone());
+        i.start_profiling();
+        while !matches!(i.next_instruction().unwrap(), InterpreterEvent::ProgramEnd) {}
+        let profile = i.stop_profiling().unwrap();
+        assert_eq!(i.debug_get_stack(), [8]);
+
+        let report = ProfileReport::new(&profile, &program, Some(&map));
+        // :main runs GOTOTARGET, PUSH1, 2 x (PUSH8, CALL) and HALT
+        assert_eq!(
+            report.by_label,
+            [(":double".to_string(), 8), (":main".to_string(), 7)]
+        );
+        assert_eq!(report.by_opcode[0], ("GOTOTARGET".to_string(), 3));
+        assert_eq!(report.total, 15);
+
+        assert_eq!(
+            collapsed_stacks(&profile, Some(&map)),
+            ":main 7\n:main;:double 8\n"
+        );
+    }
+}